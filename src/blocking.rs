@@ -0,0 +1,169 @@
+/*!
+Adapts a blocking operation (such as a libcurl transfer) into a future.
+
+The operation runs on its own thread.  Dropping the future before it completes
+flags the operation as cancelled; the operation is expected to poll [Cancellation::is_cancelled],
+or register a hook with [Cancellation::on_cancel] that unblocks it.
+*/
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::task::{Context, Poll, Waker};
+
+struct State<T> {
+    result: Option<std::thread::Result<T>>,
+    waker: Option<Waker>,
+}
+
+type Hook = Box<dyn FnOnce() + Send>;
+
+struct CancelState {
+    cancelled: AtomicBool,
+    ///Hooks to run on cancel, by [HookId].  `None` once the operation has finished.
+    hooks: Mutex<Option<Vec<Option<Hook>>>>,
+}
+
+///Identifies a hook registered with [Cancellation::on_cancel], so it can be removed.
+#[derive(Debug,Clone,Copy)]
+pub(crate) struct HookId(usize);
+
+///Lets the blocking operation find out whether anyone still cares about the result.
+#[derive(Clone)]
+pub(crate) struct Cancellation(Arc<CancelState>);
+impl Cancellation {
+    fn new() -> Self {
+        Cancellation(Arc::new(CancelState {
+            cancelled: AtomicBool::new(false),
+            hooks: Mutex::new(Some(Vec::new())),
+        }))
+    }
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::Relaxed)
+    }
+    ///Runs `hook` if the operation is cancelled before it finishes.
+    ///
+    /// If the operation was already cancelled, `hook` runs immediately.  Returns `None` if `hook` won't run later,
+    /// either because it already has or because the operation has finished.
+    #[allow(dead_code)] //not every backend needs a hook
+    pub fn on_cancel<F: FnOnce() + Send + 'static>(&self, hook: F) -> Option<HookId> {
+        let mut hooks = self.0.hooks.lock().unwrap();
+        match hooks.as_mut() {
+            Some(hooks) if !self.is_cancelled() => {
+                hooks.push(Some(Box::new(hook)));
+                Some(HookId(hooks.len() - 1))
+            }
+            Some(_) => {
+                drop(hooks);
+                hook();
+                None
+            }
+            None => None,
+        }
+    }
+    ///Removes a hook registered with [Cancellation::on_cancel], for when what it refers to is handed on to
+    /// someone else.
+    ///
+    /// Returns `false` if the operation has been cancelled, in which case the hook may have run already.
+    #[allow(dead_code)]
+    pub fn remove_hook(&self, id: HookId) -> bool {
+        let mut hooks = self.0.hooks.lock().unwrap();
+        if let Some(hook) = hooks.as_mut().and_then(|hooks| hooks.get_mut(id.0)) {
+            hook.take();
+        }
+        !self.is_cancelled()
+    }
+    fn cancel(&self) {
+        self.0.cancelled.store(true, Ordering::Relaxed);
+        //leave the list in place, so later hooks see the cancel and run immediately
+        let hooks = self.0.hooks.lock().unwrap().as_mut().map(std::mem::take);
+        for hook in hooks.into_iter().flatten().flatten() {
+            hook()
+        }
+    }
+    fn finish(&self) {
+        self.0.hooks.lock().unwrap().take();
+    }
+}
+
+struct Shared<T> {
+    state: Mutex<State<T>>,
+}
+
+pub(crate) struct BlockingFuture<T> {
+    shared: Arc<Shared<T>>,
+    cancellation: Cancellation,
+}
+
+///Runs `f` on a new thread, returning a future for its result.
+pub(crate) fn spawn<T: Send + 'static, F: FnOnce(Cancellation) -> T + Send + 'static>(f: F) -> BlockingFuture<T> {
+    let cancellation = Cancellation::new();
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State{result: None, waker: None}),
+    });
+    let move_shared = shared.clone();
+    let move_cancellation = cancellation.clone();
+    std::thread::Builder::new().name("requestr".to_owned()).spawn(move || {
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| f(move_cancellation.clone())));
+        //resources the hooks refer to may be reused after this point
+        move_cancellation.finish();
+        let waker = {
+            let mut state = move_shared.state.lock().unwrap();
+            state.result = Some(result);
+            state.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }).expect("Can't spawn thread");
+    BlockingFuture { shared, cancellation }
+}
+
+impl<T> Future for BlockingFuture<T> {
+    type Output = T;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let mut state = self.shared.state.lock().unwrap();
+        match state.result.take() {
+            Some(Ok(result)) => Poll::Ready(result),
+            //re-raise panics from the worker on the awaiting thread
+            Some(Err(panic)) => {
+                drop(state);
+                std::panic::resume_unwind(panic)
+            }
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<T> Drop for BlockingFuture<T> {
+    fn drop(&mut self) {
+        self.cancellation.cancel();
+    }
+}
+
+#[cfg(test)] mod test {
+    use super::Cancellation;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test] fn hooks() {
+        let ran = Arc::new(AtomicUsize::new(0));
+        let cancellation = Cancellation::new();
+        let counter = |ran: &Arc<AtomicUsize>| {
+            let ran = ran.clone();
+            move || { ran.fetch_add(1, Ordering::Relaxed); }
+        };
+        let removed = cancellation.on_cancel(counter(&ran)).unwrap();
+        cancellation.on_cancel(counter(&ran)).unwrap();
+        assert!(cancellation.remove_hook(removed));
+        cancellation.cancel();
+        assert_eq!(ran.load(Ordering::Relaxed), 1);
+        //too late to remove, and later hooks run immediately
+        assert!(!cancellation.remove_hook(removed));
+        assert!(cancellation.on_cancel(counter(&ran)).is_none());
+        assert_eq!(ran.load(Ordering::Relaxed), 2);
+    }
+}
@@ -0,0 +1,199 @@
+/*!
+Request bodies, held in memory or read from files as they're sent.
+
+Most bodies are bytes in memory, set with [crate::Request::body] or [crate::Request::form].  A
+[crate::Multipart] form with file parts is instead a sequence of bytes and files, which is only read while it's
+being sent.  A [crate::transport::Transport] reads either kind with [Body::reader].
+*/
+use std::fs::File;
+use std::io::{ErrorKind, Read, Take};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+///The body of a request.  See the [module documentation](self).
+///
+/// Files are opened again each time the body is read, so a request with a streamed body can still be retried or
+/// redirected.
+#[derive(Debug,Clone)]
+pub struct Body(Inner);
+
+#[derive(Debug,Clone)]
+enum Inner {
+    Bytes(Box<[u8]>),
+    Stream { segments: Arc<[Segment]>, len: u64 },
+}
+
+///One piece of a streamed body.
+#[derive(Debug)]
+pub(crate) enum Segment {
+    Bytes(Box<[u8]>),
+    ///The first `len` bytes of the file at `path`.
+    File { path: PathBuf, len: u64 },
+}
+impl Segment {
+    fn len(&self) -> u64 {
+        match self {
+            Segment::Bytes(bytes) => bytes.len() as u64,
+            Segment::File { len, .. } => *len,
+        }
+    }
+}
+
+impl Body {
+    ///A body made of `segments`, in order.  Without any files, it's held in memory.
+    pub(crate) fn from_segments(segments: Vec<Segment>) -> Body {
+        if segments.iter().all(|s| matches!(s, Segment::Bytes(_))) {
+            let bytes: Vec<u8> = segments.iter().flat_map(|s| match s {
+                Segment::Bytes(bytes) => bytes.iter().copied(),
+                Segment::File { .. } => unreachable!(),
+            }).collect();
+            return Body(Inner::Bytes(bytes.into_boxed_slice()));
+        }
+        let len = segments.iter().map(Segment::len).sum();
+        Body(Inner::Stream { segments: segments.into(), len })
+    }
+    ///The length in bytes, as sent in `Content-Length`.
+    pub fn len(&self) -> u64 {
+        match &self.0 {
+            Inner::Bytes(bytes) => bytes.len() as u64,
+            Inner::Stream { len, .. } => *len,
+        }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    ///The body, if it's held in memory.  `None` if any of it is read from files.
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match &self.0 {
+            Inner::Bytes(bytes) => Some(bytes),
+            Inner::Stream { .. } => None,
+        }
+    }
+    ///Reads from the start of the body, opening each file as it gets to it.
+    pub fn reader(&self) -> BodyReader {
+        let segments = match &self.0 {
+            Inner::Bytes(bytes) => Arc::from([Segment::Bytes(bytes.clone())]),
+            Inner::Stream { segments, .. } => segments.clone(),
+        };
+        BodyReader { segments, index: 0, offset: 0, file: None }
+    }
+    ///Reads the whole body into memory.
+    ///
+    /// # Errors
+    /// An I/O error if a file can't be read, or has become shorter since it was added.
+    pub fn to_vec(&self) -> std::io::Result<Vec<u8>> {
+        if let Some(bytes) = self.as_bytes() {
+            return Ok(bytes.to_vec());
+        }
+        let mut vec = Vec::with_capacity(self.len() as usize);
+        self.reader().read_to_end(&mut vec)?;
+        Ok(vec)
+    }
+    ///The bytes of a body held in memory, without copying them.  A body read from files is given back.
+    #[cfg_attr(not(any(target_os = "macos", target_os = "windows")), allow(dead_code))]
+    pub(crate) fn into_bytes(self) -> Result<Box<[u8]>,Body> {
+        match self.0 {
+            Inner::Bytes(bytes) => Ok(bytes),
+            inner => Err(Body(inner)),
+        }
+    }
+}
+impl From<Box<[u8]>> for Body {
+    fn from(bytes: Box<[u8]>) -> Body {
+        Body(Inner::Bytes(bytes))
+    }
+}
+impl From<Vec<u8>> for Body {
+    fn from(bytes: Vec<u8>) -> Body {
+        Body(Inner::Bytes(bytes.into_boxed_slice()))
+    }
+}
+
+///Reads a [Body].  See [Body::reader].
+///
+/// A file that has become shorter since it was added fails the read with [ErrorKind::UnexpectedEof]; one that has
+/// grown is cut off, so the body is always the length that was announced.
+pub struct BodyReader {
+    segments: Arc<[Segment]>,
+    ///The segment being read
+    index: usize,
+    ///How far into a bytes segment we are
+    offset: usize,
+    ///The open file, for a file segment
+    file: Option<Take<File>>,
+}
+impl Read for BodyReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            let Some(segment) = self.segments.get(self.index) else {
+                return Ok(0);
+            };
+            let read = match segment {
+                Segment::Bytes(bytes) => {
+                    let read = (&bytes[self.offset..]).read(buf)?;
+                    self.offset += read;
+                    read
+                }
+                Segment::File { path, len } => {
+                    let file = match &mut self.file {
+                        Some(file) => file,
+                        None => self.file.insert(File::open(path)?.take(*len)),
+                    };
+                    let read = file.read(buf)?;
+                    if read == 0 && file.limit() > 0 && !buf.is_empty() {
+                        return Err(std::io::Error::new(ErrorKind::UnexpectedEof, format!("{} became shorter while it was being sent", path.display())));
+                    }
+                    read
+                }
+            };
+            if read > 0 || buf.is_empty() {
+                return Ok(read);
+            }
+            self.index += 1;
+            self.offset = 0;
+            self.file = None;
+        }
+    }
+}
+
+#[cfg(test)] mod test {
+    use super::{Body, Segment};
+    use std::io::{ErrorKind, Read};
+
+    #[test] fn segments() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("part");
+        std::fs::write(&path, b"file contents, and more").unwrap();
+        let body = Body::from_segments(vec![
+            Segment::Bytes(Box::new(*b"<")),
+            Segment::File { path: path.clone(), len: 13 },
+            Segment::Bytes(Box::new(*b">")),
+        ]);
+        assert_eq!(body.len(), 15);
+        assert_eq!(body.as_bytes(), None);
+        //read in small pieces, so reads end at segment boundaries
+        let mut read = Vec::new();
+        let mut reader = body.reader();
+        let mut buf = [0; 4];
+        loop {
+            match reader.read(&mut buf).unwrap() {
+                0 => break,
+                n => read.extend_from_slice(&buf[..n]),
+            }
+        }
+        assert_eq!(read, b"<file contents>");
+        //readers start over
+        assert_eq!(body.to_vec().unwrap(), b"<file contents>");
+
+        std::fs::write(&path, b"file").unwrap();
+        assert_eq!(body.to_vec().unwrap_err().kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test] fn in_memory() {
+        let body = Body::from_segments(vec![Segment::Bytes(Box::new(*b"a")), Segment::Bytes(Box::new(*b"b"))]);
+        assert_eq!(body.as_bytes(), Some(&b"ab"[..]));
+        let mut read = Vec::new();
+        body.reader().read_to_end(&mut read).unwrap();
+        assert_eq!(read, b"ab");
+    }
+}
@@ -0,0 +1,108 @@
+/*!
+Cancelling requests from elsewhere.
+
+Dropping the future returned by [crate::Request::perform] or [crate::Request::download] cancels the request on every
+backend.  When the future is owned by another task, use [crate::Request::perform_cancellable] instead, and call
+[CancelHandle::cancel] on the handle it returns: the future then completes with [crate::Error::Cancelled].
+
+```
+use std::time::Duration;
+use requestr::{Request, Error, StatusCode};
+use requestr::mock::{MockTransport, Matcher};
+use pcore::pstr;
+# pcore::release_pool::autoreleasepool(|pool| {
+# let mock = std::sync::Arc::new(MockTransport::new());
+# mock.on(Matcher::any(), StatusCode::OK, b"");
+let (future, handle) = Request::new(pstr!("https://example.com/"), pool).unwrap()
+#   .transport(mock)
+    .perform_cancellable(pool);
+std::thread::spawn(move || handle.cancel()).join().unwrap();
+let result = kiruna::test::test_await(future, Duration::from_secs(1));
+assert!(matches!(result, Err(Error::Cancelled)));
+# });
+```
+*/
+use std::future::Future;
+use std::pin::pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use crate::Error;
+
+#[derive(Debug,Default)]
+struct State {
+    cancelled: bool,
+    wakers: Vec<Waker>,
+}
+
+///Cancels the request it came with.  Clones cancel the same request, and can be sent to other threads.
+#[derive(Debug,Clone,Default)]
+pub struct CancelHandle(Arc<Mutex<State>>);
+impl CancelHandle {
+    pub(crate) fn new() -> Self {
+        CancelHandle::default()
+    }
+    ///Cancels the request, if it hasn't finished.  Its future completes with [Error::Cancelled].
+    pub fn cancel(&self) {
+        let wakers = {
+            let mut state = self.0.lock().unwrap();
+            state.cancelled = true;
+            std::mem::take(&mut state.wakers)
+        };
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+    ///Whether [CancelHandle::cancel] has been called.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.lock().unwrap().cancelled
+    }
+    fn poll_cancelled(&self, cx: &mut Context<'_>) -> Poll<()> {
+        let mut state = self.0.lock().unwrap();
+        if state.cancelled {
+            return Poll::Ready(());
+        }
+        if !state.wakers.iter().any(|w| w.will_wake(cx.waker())) {
+            state.wakers.push(cx.waker().clone());
+        }
+        Poll::Pending
+    }
+}
+
+///Runs `future`, failing with [Error::Cancelled] if `handle` is cancelled first.
+///
+/// On cancellation, `future` is dropped, which cancels the request.
+pub(crate) async fn cancellable<T, F: Future<Output=Result<T,Error>>>(handle: Option<CancelHandle>, future: F) -> Result<T,Error> {
+    let mut future = pin!(future);
+    std::future::poll_fn(|cx| {
+        if handle.as_ref().is_some_and(|h| h.poll_cancelled(cx).is_ready()) {
+            return Poll::Ready(Err(Error::Cancelled));
+        }
+        future.as_mut().poll(cx)
+    }).await
+}
+
+#[cfg(test)] mod test {
+    use crate::{Request, Error, StatusCode};
+    use crate::mock::{MockTransport, Matcher};
+    use pcore::pstr;
+    use pcore::release_pool::autoreleasepool;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test] fn cancel_before_sending() {
+        autoreleasepool(|pool| {
+            let mock = Arc::new(MockTransport::new());
+            mock.on(Matcher::any(), StatusCode::OK, b"");
+            let (future, handle) = Request::new(pstr!("https://example.com/"), pool).unwrap()
+                .transport(mock.clone())
+                .download_cancellable(pool);
+            let clone = handle.clone();
+            assert!(!handle.is_cancelled());
+            clone.cancel();
+            assert!(handle.is_cancelled());
+            let result = kiruna::test::test_await(future, Duration::from_secs(1));
+            assert!(matches!(result, Err(Error::Cancelled)));
+            mock.assert_calls(&Matcher::any(), 0);
+        });
+    }
+}
@@ -0,0 +1,409 @@
+/*!
+Record/replay ("cassette") transport for deterministic integration tests.
+
+In record mode, [CassetteTransport] forwards each request to a real transport and appends the request and
+its response to a cassette file.  In replay mode it answers [crate::Request::perform] and
+[crate::Request::download] from that file alone, so a suite recorded once against a live API runs offline
+afterwards.
+
+```no_run
+use std::sync::Arc;
+use requestr::Request;
+use requestr::cassette::CassetteTransport;
+use pcore::pstr;
+# pcore::release_pool::autoreleasepool(|pool| {
+//replays if the file exists, otherwise records real traffic into it
+let cassette = Arc::new(CassetteTransport::open("tests/cassettes/api.cassette").unwrap());
+let future = Request::new(pstr!("https://example.com/api"), pool).unwrap()
+    .transport(cassette)
+    .perform(pool);
+# });
+```
+
+# Matching
+A replayed request matches a recorded one when the method, URL and body are equal.  Headers are recorded
+but not compared, so credentials may change between recording and replay.  Each recorded response is served
+once, in recording order; a request with no unused match fails with [Error::Unmatched].
+
+# Credentials
+Cassettes tend to end up under version control, so by default the values of `Authorization`,
+`Proxy-Authorization`, `Cookie` and `Set-Cookie` are stored as `[redacted]` (see [redact_credentials]).  Anything
+else, including API keys sent in other fields or in the URL, is stored as sent.  Use
+[CassetteTransport::filter_headers] to redact or drop more fields.
+
+# Format
+The cassette is a text file, so it diffs reasonably under version control.  Bodies are stored raw, preceded
+by their length:
+
+```text
+requestr-cassette 1
+request POST https://example.com/api
+header Accept: text/plain
+body 5
+hello
+response 201
+header Content-Type: text/plain
+body 7
+created
+```
+*/
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use crate::{Error, Response, Downloaded, StatusCode};
+use crate::request::RequestDescription;
+use crate::header::HeaderMap;
+use crate::transport::{Transport, BoxFuture, download_with_perform, default_transport};
+
+const MAGIC: &str = "requestr-cassette 1";
+
+///Decides what's recorded for each header field: the value to store, or `None` to leave the field out.
+type HeaderFilter = dyn Fn(&str, &str) -> Option<String> + Send + Sync;
+
+///The default [CassetteTransport::filter_headers]: stores `[redacted]` in place of the value of `Authorization`,
+/// `Proxy-Authorization`, `Cookie` and `Set-Cookie`, and every other field as it is.
+pub fn redact_credentials(name: &str, value: &str) -> Option<String> {
+    let secret = ["Authorization", "Proxy-Authorization", "Cookie", "Set-Cookie"].iter().any(|s| s.eq_ignore_ascii_case(name));
+    Some(if secret { "[redacted]".to_owned() } else { value.to_owned() })
+}
+
+fn filter(headers: &HeaderMap, filter: &HeaderFilter) -> HeaderMap {
+    headers.iter().filter_map(|(name, value)| filter(name, value).map(|value| (name.to_owned(), value))).collect()
+}
+
+#[derive(Debug,Clone,PartialEq)]
+struct Interaction {
+    method: String,
+    url: String,
+    headers: HeaderMap,
+    request_body: Option<Vec<u8>>,
+    code: StatusCode,
+    response_headers: HeaderMap,
+    body: Vec<u8>,
+}
+impl Interaction {
+    fn new(request: &RequestDescription, code: StatusCode, response_headers: &HeaderMap, body: Vec<u8>, header_filter: &HeaderFilter) -> Self {
+        Interaction {
+            method: request.method().to_string(),
+            url: request.url().to_string(),
+            headers: filter(request.headers(), header_filter),
+            //a body that can't be read fails the request anyway
+            request_body: request.body().and_then(|b| b.to_vec().ok()),
+            code,
+            response_headers: filter(response_headers, header_filter),
+            body,
+        }
+    }
+    fn matches(&self, request: &RequestDescription) -> bool {
+        self.method == request.method().as_str() && self.url == request.url().as_str() &&
+            self.request_body.as_deref().unwrap_or(&[]) == request.body().and_then(|b| b.to_vec().ok()).unwrap_or_default()
+    }
+}
+
+fn write_body(out: &mut Vec<u8>, body: &[u8]) {
+    out.extend_from_slice(format!("body {}\n", body.len()).as_bytes());
+    out.extend_from_slice(body);
+    out.push(b'\n');
+}
+
+fn write_headers<'a, I: Iterator<Item=(&'a str, &'a str)>>(out: &mut Vec<u8>, headers: I) {
+    for (name, value) in headers {
+        out.extend_from_slice(format!("header {}: {}\n", name, value).as_bytes());
+    }
+}
+
+fn serialize(interactions: &[Interaction]) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(MAGIC.as_bytes());
+    out.push(b'\n');
+    for interaction in interactions {
+        out.extend_from_slice(format!("request {} {}\n", interaction.method, interaction.url).as_bytes());
+        write_headers(&mut out, interaction.headers.iter());
+        if let Some(body) = &interaction.request_body {
+            write_body(&mut out, body);
+        }
+        out.extend_from_slice(format!("response {}\n", interaction.code.as_u16()).as_bytes());
+        write_headers(&mut out, interaction.response_headers.iter());
+        write_body(&mut out, &interaction.body);
+    }
+    out
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+impl<'a> Reader<'a> {
+    fn error(&self, message: &str) -> Error {
+        Error::Io(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("cassette: {} at byte {}", message, self.position)))
+    }
+    fn peek_line(&self) -> Option<&'a str> {
+        let rest = &self.bytes[self.position..];
+        let end = rest.iter().position(|b| *b == b'\n')?;
+        std::str::from_utf8(&rest[..end]).ok()
+    }
+    fn line(&mut self) -> Result<&'a str,Error> {
+        let line = self.peek_line().ok_or_else(|| self.error("expected a line"))?;
+        self.position += line.len() + 1;
+        Ok(line)
+    }
+    fn headers(&mut self) -> Result<HeaderMap,Error> {
+        let mut headers = HeaderMap::new();
+        while let Some(header) = self.peek_line().and_then(|l| l.strip_prefix("header ")) {
+            let (name, value) = header.split_once(": ").ok_or_else(|| self.error("malformed header"))?;
+            headers.append(name, value);
+            self.line()?;
+        }
+        Ok(headers)
+    }
+    fn body(&mut self) -> Result<Vec<u8>,Error> {
+        let len = self.line()?.strip_prefix("body ").and_then(|l| l.parse::<usize>().ok())
+            .ok_or_else(|| self.error("expected body"))?;
+        let end = self.position + len;
+        if self.bytes.get(end) != Some(&b'\n') {
+            return Err(self.error("truncated body"));
+        }
+        let body = self.bytes[self.position..end].to_vec();
+        self.position = end + 1;
+        Ok(body)
+    }
+}
+
+fn parse(bytes: &[u8]) -> Result<Vec<Interaction>,Error> {
+    let mut reader = Reader { bytes, position: 0 };
+    if reader.line()? != MAGIC {
+        return Err(reader.error("not a cassette"));
+    }
+    let mut interactions = Vec::new();
+    while reader.position < bytes.len() {
+        let (method, url) = reader.line()?.strip_prefix("request ").and_then(|l| l.split_once(' '))
+            .ok_or_else(|| reader.error("expected request"))?;
+        let headers = reader.headers()?;
+        let request_body = match reader.peek_line() {
+            Some(l) if l.starts_with("body ") => Some(reader.body()?),
+            _ => None,
+        };
+        let code = reader.line()?.strip_prefix("response ").and_then(|l| l.parse().ok()).and_then(StatusCode::new)
+            .ok_or_else(|| reader.error("expected response"))?;
+        let response_headers = reader.headers()?;
+        let body = reader.body()?;
+        interactions.push(Interaction { method: method.to_owned(), url: url.to_owned(), headers, request_body, code, response_headers, body });
+    }
+    Ok(interactions)
+}
+
+struct Recorder {
+    path: PathBuf,
+    interactions: Mutex<Vec<Interaction>>,
+}
+impl Recorder {
+    ///Appends the interaction and rewrites the file, so the cassette is complete even if the process dies.
+    fn push(&self, interaction: Interaction) -> Result<(),Error> {
+        let mut interactions = self.interactions.lock().unwrap();
+        interactions.push(interaction);
+        std::fs::write(&self.path, serialize(&interactions)).map_err(Error::Io)
+    }
+}
+
+enum Mode {
+    Record {
+        inner: Arc<dyn Transport>,
+        recorder: Arc<Recorder>,
+        filter: Arc<HeaderFilter>,
+    },
+    Replay {
+        interactions: Vec<Interaction>,
+        used: Mutex<Vec<bool>>,
+    },
+}
+
+///A [Transport] that records traffic to, or replays it from, a cassette file.
+pub struct CassetteTransport {
+    mode: Mode,
+}
+impl CassetteTransport {
+    ///Forwards requests to `inner`, recording each exchange to a new cassette at `path`.
+    ///
+    /// Any existing file at `path` is replaced.
+    pub fn record<P: AsRef<Path>>(path: P, inner: Arc<dyn Transport>) -> Result<Self,Error> {
+        let path = path.as_ref().to_owned();
+        std::fs::write(&path, serialize(&[])).map_err(Error::Io)?;
+        Ok(CassetteTransport {
+            mode: Mode::Record { inner, recorder: Arc::new(Recorder { path, interactions: Mutex::new(Vec::new()) }), filter: Arc::new(redact_credentials) }
+        })
+    }
+    ///Sets what's recorded for each request and response header field, in place of [redact_credentials].
+    ///
+    /// `filter` is called with each field's name and value, and returns the value to store, or `None` to leave the
+    /// field out.  Replayed responses have the stored fields.  Has no effect when replaying.
+    ///
+    /// ```no_run
+    /// use requestr::cassette::{CassetteTransport, redact_credentials};
+    /// let cassette = CassetteTransport::open("tests/cassettes/api.cassette").unwrap()
+    ///     .filter_headers(|name, value| match name.eq_ignore_ascii_case("X-Api-Key") {
+    ///         true => None,
+    ///         false => redact_credentials(name, value),
+    ///     });
+    /// ```
+    pub fn filter_headers<F: Fn(&str, &str) -> Option<String> + Send + Sync + 'static>(mut self, header_filter: F) -> Self {
+        if let Mode::Record { filter, .. } = &mut self.mode {
+            *filter = Arc::new(header_filter);
+        }
+        self
+    }
+    ///Serves requests from the cassette at `path`, without touching the network.
+    pub fn replay<P: AsRef<Path>>(path: P) -> Result<Self,Error> {
+        let interactions = parse(&std::fs::read(path).map_err(Error::Io)?)?;
+        let used = Mutex::new(vec![false; interactions.len()]);
+        Ok(CassetteTransport { mode: Mode::Replay { interactions, used } })
+    }
+    ///Replays the cassette at `path` if it exists, otherwise records one with [default_transport].
+    ///
+    /// Delete the file to re-record.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self,Error> {
+        if path.as_ref().exists() {
+            CassetteTransport::replay(path)
+        }
+        else {
+            CassetteTransport::record(path, default_transport())
+        }
+    }
+}
+impl Transport for CassetteTransport {
+    fn perform(&self, request: RequestDescription) -> BoxFuture<Result<Response,Error>> {
+        match &self.mode {
+            Mode::Record { inner, recorder, filter } => {
+                let recorder = recorder.clone();
+                let filter = filter.clone();
+                let description = request.clone();
+                let response = inner.perform(request);
+                Box::pin(async move {
+                    let response = response.await?;
+                    recorder.push(Interaction::new(&description, response.status(), response.headers(), response.data().as_slice().to_vec(), &*filter))?;
+                    Ok(response)
+                })
+            }
+            Mode::Replay { interactions, used } => {
+                let mut used = used.lock().unwrap();
+                let found = interactions.iter().enumerate().find(|(i, interaction)| !used[*i] && interaction.matches(&request));
+                let result = match found {
+                    Some((i, interaction)) => {
+                        used[i] = true;
+                        Ok(Response::new(interaction.code, interaction.body.clone().into()).with_headers(interaction.response_headers.clone()))
+                    }
+                    None => Err(Error::Unmatched(format!("{} {}", request.method(), request.url()))),
+                };
+                Box::pin(std::future::ready(result))
+            }
+        }
+    }
+    fn download(&self, request: RequestDescription) -> BoxFuture<Result<Downloaded,Error>> {
+        match &self.mode {
+            Mode::Record { inner, recorder, filter } => {
+                let recorder = recorder.clone();
+                let filter = filter.clone();
+                let description = request.clone();
+                let downloaded = inner.download(request);
+                Box::pin(async move {
+                    let downloaded = downloaded.await?;
+                    let body = std::fs::read(downloaded.copy_path()).map_err(Error::Io)?;
+                    recorder.push(Interaction::new(&description, downloaded.status(), downloaded.headers(), body, &*filter))?;
+                    Ok(downloaded)
+                })
+            }
+            Mode::Replay { .. } => download_with_perform(self, request),
+        }
+    }
+}
+
+#[cfg(test)] mod test {
+    use super::{CassetteTransport, Interaction, serialize, parse};
+    use crate::header::HeaderMap;
+    use crate::mock::{MockTransport, Matcher};
+    use crate::{Request, Response, Error, Method, StatusCode};
+    use pcore::pstr;
+    use pcore::release_pool::autoreleasepool;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test] fn round_trip() {
+        let interactions = vec![
+            Interaction { method: "POST".to_owned(), url: "http://a/b".to_owned(), headers: [("A", "b: c")].into_iter().collect(),
+                request_body: Some(b"line\nbreak".to_vec()), code: StatusCode::CREATED,
+                response_headers: [("ETag", "\"1\""), ("Link", "a"), ("Link", "b")].into_iter().collect(), body: vec![0, 10, 255] },
+            Interaction { method: "GET".to_owned(), url: "http://a/".to_owned(), headers: HeaderMap::new(),
+                request_body: None, code: StatusCode::NOT_FOUND, response_headers: HeaderMap::new(), body: vec![] },
+        ];
+        assert_eq!(parse(&serialize(&interactions)).unwrap(), interactions);
+        assert!(parse(b"requestr-cassette 1\nrequest GET http://a/\nresponse 200\nbody 10\nshort\n").is_err());
+    }
+
+    #[test] fn record_then_replay() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("api.cassette");
+        let mock = Arc::new(MockTransport::new());
+        mock.on_with(Matcher::any().url("https://example.com/first"), |_| {
+            Ok(Response::new(StatusCode::OK, b"one".to_vec().into()).with_headers([("Set-Cookie", "session=1"), ("ETag", "\"1\"")].into_iter().collect()))
+        });
+        mock.on(Matcher::any().url("https://example.com/second.bin"), StatusCode::CREATED, &[0, 1, 2]);
+
+        let run = |transport: Arc<CassetteTransport>| autoreleasepool(|pool| {
+            let future = Request::new(pstr!("https://example.com/first"), pool).unwrap()
+                .header(pstr!("Authorization"), Some(pstr!("token secret")), pool)
+                .transport(transport.clone())
+                .perform(pool);
+            let response = kiruna::test::test_await(future, Duration::from_secs(1)).unwrap();
+            assert_eq!(response.check_status().unwrap().as_slice(), b"one");
+            let future = Request::new(pstr!("https://example.com/second.bin"), pool).unwrap()
+                .method(Method::Put)
+                .body(Box::new(*b"upload"))
+                .transport(transport.clone())
+                .download(pool);
+            let downloaded = kiruna::test::test_await(future, Duration::from_secs(1)).unwrap();
+            assert_eq!(std::fs::read(downloaded.copy_path()).unwrap(), [0, 1, 2]);
+        });
+
+        run(Arc::new(CassetteTransport::record(&path, mock.clone()).unwrap()));
+        mock.assert_calls(&Matcher::any(), 2);
+        let recorded = String::from_utf8_lossy(&std::fs::read(&path).unwrap()).into_owned();
+        assert!(recorded.contains("header Authorization: [redacted]\n") && recorded.contains("header Set-Cookie: [redacted]\n"), "{}", recorded);
+        assert!(recorded.contains("header ETag: \"1\"\n") && !recorded.contains("secret") && !recorded.contains("session"), "{}", recorded);
+        run(Arc::new(CassetteTransport::open(&path).unwrap()));
+        //replay never reaches the inner transport
+        mock.assert_calls(&Matcher::any(), 2);
+
+        let replay = Arc::new(CassetteTransport::replay(&path).unwrap());
+        autoreleasepool(|pool| {
+            let first = || Request::new(pstr!("https://example.com/first"), pool).unwrap().transport(replay.clone()).perform(pool);
+            kiruna::test::test_await(first(), Duration::from_secs(1)).unwrap();
+            //each recorded response is served once
+            let result = kiruna::test::test_await(first(), Duration::from_secs(1));
+            assert!(matches!(result, Err(Error::Unmatched(ref r)) if r == "GET https://example.com/first"));
+            //a different body is a different request
+            let future = Request::new(pstr!("https://example.com/second.bin"), pool).unwrap()
+                .method(Method::Put)
+                .transport(replay.clone())
+                .perform(pool);
+            assert!(matches!(kiruna::test::test_await(future, Duration::from_secs(1)), Err(Error::Unmatched(_))));
+        });
+    }
+
+    #[test] fn filter_headers() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("api.cassette");
+        let mock = Arc::new(MockTransport::new());
+        mock.on(Matcher::any(), StatusCode::OK, b"");
+        let cassette = CassetteTransport::record(&path, mock).unwrap()
+            .filter_headers(|name, value| (name != "X-Api-Key").then(|| value.to_owned()));
+        autoreleasepool(|pool| {
+            let future = Request::new(pstr!("https://example.com/"), pool).unwrap()
+                .header(pstr!("X-Api-Key"), Some(pstr!("key")), pool)
+                .header(pstr!("Cookie"), Some(pstr!("kept=1")), pool)
+                .transport(Arc::new(cassette))
+                .perform(pool);
+            kiruna::test::test_await(future, Duration::from_secs(1)).unwrap();
+        });
+        let recorded = parse(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(recorded[0].headers.get("Cookie"), Some("kept=1"));
+        assert!(!recorded[0].headers.contains("X-Api-Key"));
+    }
+}
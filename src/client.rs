@@ -0,0 +1,226 @@
+/*!
+Shared configuration and connection reuse.
+
+A [Client] owns a connection pool on the platform's HTTP stack (a dedicated `NSURLSession`, one `HttpClient`, or a
+set of reusable libcurl handles) and defaults that every [Request] created from it starts with.  Clients are cheap to
+clone; clones share the pool.
+
+```
+use std::time::Duration;
+use requestr::Client;
+use pcore::pstr;
+# pcore::release_pool::autoreleasepool(|pool| {
+let client = Client::builder()
+    .header("Accept", "application/json")
+    .timeout(Duration::from_secs(30))
+    .build().unwrap();
+let request = client.request(pstr!("https://example.com/api"), pool).unwrap();
+# });
+```
+
+Requests made with [Request::new] share a default pool with default settings.
+*/
+use std::sync::Arc;
+use std::time::Duration;
+use pcore::release_pool::ReleasePool;
+use crate::{Error, Request, RetryPolicy, HeaderMap, IntoUrl};
+use crate::request::{Timeouts, DEFAULT_USER_AGENT};
+use crate::transport::Transport;
+
+///Options that belong to a connection pool rather than to a single request.
+#[derive(Debug,Clone)]
+pub(crate) struct SessionConfig {
+    pub follow_redirects: bool,
+    pub proxy: Option<String>,
+    pub accept_invalid_certs: bool,
+}
+impl Default for SessionConfig {
+    fn default() -> Self {
+        SessionConfig { follow_redirects: true, proxy: None, accept_invalid_certs: false }
+    }
+}
+
+///What each [Request] from a [Client] starts with.
+#[derive(Debug)]
+struct Defaults {
+    user_agent: Option<String>,
+    headers: HeaderMap,
+    timeouts: Timeouts,
+    retry: Option<RetryPolicy>,
+}
+impl Default for Defaults {
+    fn default() -> Self {
+        Defaults {
+            user_agent: Some(DEFAULT_USER_AGENT.to_owned()),
+            headers: HeaderMap::new(),
+            timeouts: Timeouts::default(),
+            retry: None,
+        }
+    }
+}
+
+///Builds a [Client].  See [Client::builder].
+#[derive(Default)]
+pub struct ClientBuilder {
+    session: SessionConfig,
+    defaults: Defaults,
+    transport: Option<Arc<dyn Transport>>,
+}
+impl ClientBuilder {
+    ///Adds a header field to every request.  A request can replace it with [Request::header].
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.defaults.headers.insert(name, value);
+        self
+    }
+    ///Sets (or with `None`, removes) the `User-Agent` of every request.  See [Request::user_agent].
+    pub fn user_agent(mut self, user_agent: Option<&str>) -> Self {
+        self.defaults.user_agent = user_agent.map(|u| u.to_owned());
+        self
+    }
+    ///The default for [Request::timeout].
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.defaults.timeouts.total = Some(timeout);
+        self
+    }
+    ///The default for [Request::connect_timeout].
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.defaults.timeouts.connect = Some(timeout);
+        self
+    }
+    ///The default for [Request::read_idle_timeout].
+    pub fn read_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.defaults.timeouts.read_idle = Some(timeout);
+        self
+    }
+    ///The default for [Request::retry].
+    pub fn retry(mut self, policy: RetryPolicy) -> Self {
+        self.defaults.retry = Some(policy);
+        self
+    }
+    ///Whether to follow redirects.  On by default.
+    ///
+    /// When off, a redirect is returned as the response.  `NSURLSession` always follows redirects, so there
+    /// [ClientBuilder::build] fails with [Error::Unsupported].
+    pub fn follow_redirects(mut self, follow: bool) -> Self {
+        self.session.follow_redirects = follow;
+        self
+    }
+    ///Sends requests through the proxy at `url`, such as `http://proxy.example:8080`.
+    ///
+    /// Supported by the libcurl and std backends.  `NSURLSession` and `HttpClient` only use the system's proxy settings,
+    /// so there [ClientBuilder::build] fails with [Error::Unsupported].
+    pub fn proxy(mut self, url: &str) -> Self {
+        self.session.proxy = Some(url.to_owned());
+        self
+    }
+    ///Accepts TLS certificates that can't be verified, such as self-signed ones.
+    ///
+    /// This defeats the point of TLS; only use it for testing.  Supported by the libcurl and `HttpClient` backends;
+    /// with `NSURLSession`, [ClientBuilder::build] fails with [Error::Unsupported].
+    pub fn danger_accept_invalid_certs(mut self, accept: bool) -> Self {
+        self.session.accept_invalid_certs = accept;
+        self
+    }
+    ///Uses `transport` instead of a new connection pool on the platform's stack.
+    ///
+    /// The options for redirects, proxies and certificates belong to the pool, so they don't apply.
+    pub fn transport(mut self, transport: Arc<dyn Transport>) -> Self {
+        self.transport = Some(transport);
+        self
+    }
+    ///Creates the client, and its connection pool.
+    ///
+    /// # Errors
+    /// [Error::InvalidURL] if the proxy URL is invalid, [Error::InvalidHeader] if a default header is invalid,
+    /// [Error::Unsupported] if the backend can't honor a pool option, or an error from the platform if the pool can't
+    /// be created.
+    pub fn build(self) -> Result<Client,Error> {
+        self.defaults.headers.validate()?;
+        if let Some(user_agent) = &self.defaults.user_agent {
+            crate::header::validate("User-Agent", user_agent)?;
+        }
+        let transport = match self.transport {
+            Some(transport) => transport,
+            None => crate::transport::session_transport(&self.session)?,
+        };
+        Ok(Client { defaults: Arc::new(self.defaults), transport })
+    }
+}
+
+///Creates [Request]s that share configuration and connections.  See the [module documentation](self).
+#[derive(Clone)]
+pub struct Client {
+    defaults: Arc<Defaults>,
+    transport: Arc<dyn Transport>,
+}
+impl Client {
+    ///A client with default settings and its own connection pool.
+    pub fn new() -> Result<Client,Error> {
+        Client::builder().build()
+    }
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder::default()
+    }
+    ///Creates a request for `url` with this client's defaults, sent over this client's connections.
+    ///
+    /// # Errors
+    /// As for [Request::new].
+    pub fn request<U: IntoUrl>(&self, url: U, pool: &ReleasePool) -> Result<Request,Error> {
+        let mut request = Request::new(url, pool)?
+            .transport(self.transport.clone())
+            .user_agent(self.defaults.user_agent.as_deref(), pool);
+        for (name, value) in self.defaults.headers.iter() {
+            request = request.header(name, Some(value), pool);
+        }
+        let timeouts = self.defaults.timeouts;
+        if let Some(timeout) = timeouts.total {
+            request = request.timeout(timeout);
+        }
+        if let Some(timeout) = timeouts.connect {
+            request = request.connect_timeout(timeout);
+        }
+        if let Some(timeout) = timeouts.read_idle {
+            request = request.read_idle_timeout(timeout);
+        }
+        if let Some(retry) = &self.defaults.retry {
+            request = request.retry(retry.clone());
+        }
+        Ok(request)
+    }
+    ///The transport requests from this client are sent with.
+    pub fn transport(&self) -> &Arc<dyn Transport> {
+        &self.transport
+    }
+}
+
+#[cfg(test)] mod test {
+    use super::Client;
+    use crate::{Request, StatusCode};
+    use crate::mock::{MockTransport, Matcher};
+    use pcore::pstr;
+    use pcore::release_pool::autoreleasepool;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test] fn defaults() {
+        autoreleasepool(|pool| {
+            let mock = Arc::new(MockTransport::new());
+            mock.on(Matcher::any(), StatusCode::OK, b"");
+            let client = Client::builder()
+                .user_agent(None)
+                .header("Accept", "text/plain")
+                .header("X-Team", "a")
+                .timeout(Duration::from_secs(3))
+                .transport(mock.clone())
+                .build().unwrap();
+            let request: Request = client.request(pstr!("https://example.com/"), pool).unwrap()
+                .header(pstr!("X-Team"), Some(pstr!("b")), pool);
+            assert_eq!(request.description().timeout(), Some(Duration::from_secs(3)));
+            assert_eq!(request.description().connect_timeout(), None);
+            let future = request.perform(pool);
+            kiruna::test::test_await(future, Duration::from_secs(1)).unwrap();
+            mock.assert_calls(&Matcher::any().header("accept", "text/plain").header("x-team", "b"), 1);
+            assert!(!mock.requests()[0].headers().contains("user-agent"));
+        });
+    }
+}
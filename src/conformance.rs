@@ -0,0 +1,398 @@
+/*!
+Scenarios every backend must agree on, run against each transport that compiles on the host.
+
+These guard the promise of a platform-neutral API: a scenario that passes for one backend and fails for another
+is a bug in the backend, not the test.
+*/
+use std::future::Future;
+use std::sync::Arc;
+use std::task::{Context, Wake, Waker};
+use std::time::{Duration, Instant};
+use pcore::pstr;
+use pcore::release_pool::autoreleasepool;
+use crate::{Request, Error, Version, Method, Multipart, Part};
+use crate::transport::Transport;
+use crate::test_server::{TestServer, large_body, CHUNKED_BODY};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+///Every transport available on this host, with a name for assertion messages.
+#[allow(clippy::vec_init_then_push)]
+fn transports() -> Vec<(&'static str, Arc<dyn Transport>)> {
+    #[allow(unused_mut)]
+    let mut transports: Vec<(&'static str, Arc<dyn Transport>)> = Vec::new();
+    #[cfg(target_os = "macos")]
+    transports.push(("URLSession", Arc::new(crate::transport::URLSessionTransport::new())));
+    #[cfg(target_os = "windows")]
+    transports.push(("HttpClient", Arc::new(crate::transport::HttpClientTransport::new())));
+    #[cfg(target_os = "linux")]
+    transports.push(("curl", Arc::new(crate::transport::CurlTransport::new())));
+    #[cfg(feature = "std-backend")]
+    transports.push(("std", Arc::new(crate::transport::StdTransport::new())));
+    transports
+}
+
+///Runs `scenario` once per transport, against a fresh server.
+fn each_transport<F: Fn(&'static str, Arc<dyn Transport>, &TestServer)>(scenario: F) {
+    for (name, transport) in transports() {
+        let server = TestServer::start();
+        scenario(name, transport, &server);
+    }
+}
+
+#[test] fn methods() {
+    each_transport(|name, transport, server| autoreleasepool(|pool| {
+        let propfind = Method::new("PROPFIND").unwrap();
+        for method in [Method::Get, Method::Post, Method::Put, Method::Delete, Method::Patch, Method::Options, propfind] {
+            let future = Request::new(server.url("/echo/method"), pool).unwrap()
+                .method(method.clone())
+                .transport(transport.clone())
+                .perform(pool);
+            let response = kiruna::test::test_await(future, TIMEOUT).unwrap();
+            assert_eq!(response.check_status().unwrap().as_slice(), method.as_str().as_bytes(), "{}", name);
+        }
+        let future = Request::new(server.url("/large/100"), pool).unwrap()
+            .method(Method::Head)
+            .transport(transport.clone())
+            .perform(pool);
+        let response = kiruna::test::test_await(future, TIMEOUT).unwrap();
+        assert!(response.check_status().unwrap().as_slice().is_empty(), "{}", name);
+    }));
+}
+
+#[test] fn header_round_trip() {
+    each_transport(|name, transport, server| autoreleasepool(|pool| {
+        let future = Request::new(server.url("/echo/headers"), pool).unwrap()
+            .header(pstr!("X-Custom"), Some(pstr!("a value, with spaces")), pool)
+            .header(pstr!("X-Replaced"), Some(pstr!("old")), pool)
+            .header(pstr!("X-Replaced"), Some(pstr!("new")), pool)
+            .header(pstr!("X-Removed"), Some(pstr!("gone")), pool)
+            .header(pstr!("X-Removed"), None::<&str>, pool)
+            .header(pstr!("x-case"), Some(pstr!("lower")), pool)
+            .header(pstr!("X-Case"), Some(pstr!("upper")), pool)
+            .append_header(pstr!("Accept-Encoding"), pstr!("gzip"), pool)
+            .append_header(pstr!("accept-encoding"), pstr!("identity"), pool)
+            .append_header(pstr!("Cookie"), pstr!("a=1"), pool)
+            .append_header(pstr!("Cookie"), pstr!("b=2"), pool)
+            .transport(transport)
+            .perform(pool);
+        let response = kiruna::test::test_await(future, TIMEOUT).unwrap();
+        let headers = String::from_utf8_lossy(response.check_status().unwrap().as_slice()).to_ascii_lowercase();
+        assert!(headers.contains("x-custom: a value, with spaces\r\n"), "{}: {}", name, headers);
+        assert!(headers.contains("x-replaced: new\r\n"), "{}: {}", name, headers);
+        assert!(!headers.contains("x-replaced: old"), "{}: {}", name, headers);
+        assert!(!headers.contains("x-removed"), "{}: {}", name, headers);
+        assert!(headers.contains("x-case: upper\r\n") && !headers.contains("lower"), "{}: {}", name, headers);
+        assert!(headers.contains("accept-encoding: gzip, identity\r\n"), "{}: {}", name, headers);
+        assert!(headers.contains("cookie: a=1; b=2\r\n"), "{}: {}", name, headers);
+    }));
+}
+
+#[test] fn user_agent() {
+    each_transport(|name, transport, server| autoreleasepool(|pool| {
+        let agents = |request: Request| {
+            let future = request.transport(transport.clone()).perform(pool);
+            let response = kiruna::test::test_await(future, TIMEOUT).unwrap();
+            let headers = String::from_utf8_lossy(response.check_status().unwrap().as_slice()).to_ascii_lowercase();
+            headers.lines().filter_map(|l| l.strip_prefix("user-agent:").map(|a| a.trim().to_owned())).collect::<Vec<_>>()
+        };
+        let default = agents(Request::new(server.url("/echo/headers"), pool).unwrap());
+        assert_eq!(default, [crate::DEFAULT_USER_AGENT.to_ascii_lowercase()], "{}", name);
+        let custom = agents(Request::new(server.url("/echo/headers"), pool).unwrap().user_agent(Some(pstr!("custom/1.0")), pool));
+        assert_eq!(custom, ["custom/1.0"], "{}", name);
+        let removed = agents(Request::new(server.url("/echo/headers"), pool).unwrap().user_agent(None::<&str>, pool));
+        assert!(removed.is_empty(), "{}: {:?}", name, removed);
+    }));
+}
+
+#[test] fn response_headers() {
+    each_transport(|name, transport, server| autoreleasepool(|pool| {
+        let future = Request::new(server.url("/response-headers?ETag=\"v1\"&X-Multi=a&X-Multi=b"), pool).unwrap()
+            .transport(transport.clone())
+            .perform(pool);
+        let response = kiruna::test::test_await(future, TIMEOUT).unwrap();
+        assert_eq!(response.header("etag"), Some("\"v1\""), "{}: {:?}", name, response.headers());
+        assert_eq!(response.header("CONTENT-LENGTH"), Some("0"), "{}: {:?}", name, response.headers());
+        //some stacks fold repeated fields into one
+        let multi = response.headers().get_all("x-multi").collect::<Vec<_>>().join(", ");
+        assert_eq!(multi, "a, b", "{}: {:?}", name, response.headers());
+
+        //after a redirect, the headers are the final response's
+        let future = Request::new(server.url("/redirect/1"), pool).unwrap()
+            .transport(transport)
+            .perform(pool);
+        let response = kiruna::test::test_await(future, TIMEOUT).unwrap();
+        assert_eq!(response.header("location"), None, "{}: {:?}", name, response.headers());
+    }));
+}
+
+#[test] fn binary_bodies() {
+    each_transport(|name, transport, server| autoreleasepool(|pool| {
+        let body: Vec<u8> = (0..=255).chain((0..=255).rev()).collect();
+        let future = Request::new(server.url("/echo"), pool).unwrap()
+            .method(Method::Post)
+            .body(body.clone().into_boxed_slice())
+            .transport(transport.clone())
+            .perform(pool);
+        let response = kiruna::test::test_await(future, TIMEOUT).unwrap();
+        assert_eq!(response.check_status().unwrap().as_slice(), &body[..], "{}", name);
+
+        let future = Request::new(server.url("/large/300000"), pool).unwrap()
+            .transport(transport.clone())
+            .perform(pool);
+        let response = kiruna::test::test_await(future, TIMEOUT).unwrap();
+        assert!(response.check_status().unwrap().as_slice() == &large_body(300000)[..], "{}", name);
+
+        let future = Request::new(server.url("/chunked"), pool).unwrap()
+            .transport(transport)
+            .perform(pool);
+        let response = kiruna::test::test_await(future, TIMEOUT).unwrap();
+        assert_eq!(response.check_status().unwrap().as_slice(), CHUNKED_BODY, "{}", name);
+    }));
+}
+
+#[test] fn form_bodies() {
+    each_transport(|name, transport, server| autoreleasepool(|pool| {
+        let pairs = [("q", "a b&c"), ("q", "ü"), ("empty", "")];
+        let future = Request::new(server.url("/echo"), pool).unwrap()
+            .method(Method::Post)
+            .form(pairs, pool)
+            .transport(transport.clone())
+            .perform(pool);
+        let response = kiruna::test::test_await(future, TIMEOUT).unwrap();
+        assert_eq!(response.check_status().unwrap().as_slice(), b"q=a+b%26c&q=%C3%BC&empty=", "{}", name);
+
+        let future = Request::new(server.url("/echo/headers"), pool).unwrap()
+            .method(Method::Post)
+            .form(pairs, pool)
+            .transport(transport)
+            .perform(pool);
+        let response = kiruna::test::test_await(future, TIMEOUT).unwrap();
+        let headers = String::from_utf8_lossy(response.check_status().unwrap().as_slice()).to_ascii_lowercase();
+        let types: Vec<_> = headers.lines().filter(|l| l.starts_with("content-type:")).collect();
+        assert_eq!(types, ["content-type: application/x-www-form-urlencoded"], "{}", name);
+        assert!(headers.contains("content-length: 25\r\n"), "{}: {}", name, headers);
+    }));
+}
+
+#[test] fn multipart_uploads() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("upload.bin");
+    std::fs::write(&path, large_body(200000)).unwrap();
+    each_transport(|name, transport, server| autoreleasepool(|pool| {
+        let form = Multipart::new()
+            .text("title", "large")
+            .part("file", Part::file(&path).unwrap().mime_type("application/x-test"));
+        let boundary = form.boundary().to_owned();
+        let future = Request::new(server.url("/echo"), pool).unwrap()
+            .method(Method::Post)
+            .multipart(form).unwrap()
+            .transport(transport.clone())
+            .perform(pool);
+        let response = kiruna::test::test_await(future, TIMEOUT).unwrap();
+        let expected = [
+            format!("--{}\r\nContent-Disposition: form-data; name=\"title\"\r\n\r\nlarge\r\n", boundary).into_bytes(),
+            format!("--{}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"upload.bin\"\r\n", boundary).into_bytes(),
+            b"Content-Type: application/x-test\r\n\r\n".to_vec(),
+            large_body(200000),
+            format!("\r\n--{}--\r\n", boundary).into_bytes(),
+        ].concat();
+        assert!(response.check_status().unwrap().as_slice() == &expected[..], "{}", name);
+
+        //the length was announced when the part was added
+        let shrinking = dir.path().join("shrinking.bin");
+        std::fs::write(&shrinking, b"0123456789").unwrap();
+        let form = Multipart::new().file("file", &shrinking).unwrap();
+        std::fs::write(&shrinking, b"01234").unwrap();
+        let future = Request::new(server.url("/echo"), pool).unwrap()
+            .method(Method::Post)
+            .multipart(form).unwrap()
+            .transport(transport)
+            .perform(pool);
+        let result = kiruna::test::test_await(future, TIMEOUT);
+        assert!(matches!(result, Err(Error::Io(_))), "{}: {:?}", name, result.map(|r| r.status()));
+    }));
+}
+
+#[test] fn status_mapping() {
+    each_transport(|name, transport, server| autoreleasepool(|pool| {
+        for code in [200u16, 201, 204, 299, 400, 404, 500, 503] {
+            let future = Request::new(server.url(&format!("/status/{}", code)), pool).unwrap()
+                .transport(transport.clone())
+                .perform(pool);
+            let response = kiruna::test::test_await(future, TIMEOUT).unwrap();
+            match response.check_status() {
+                Ok(_) => assert!((200..300).contains(&code), "{}: {} is not success", name, code),
+                Err((actual, _)) => assert_eq!(actual, code, "{}", name),
+            }
+            let future = Request::new(server.url(&format!("/status/{}", code)), pool).unwrap()
+                .transport(transport.clone())
+                .download(pool);
+            let downloaded = kiruna::test::test_await(future, TIMEOUT).unwrap();
+            match downloaded.check_status() {
+                Ok(()) => assert!((200..300).contains(&code), "{}: {} is not success", name, code),
+                Err(Error::StatusCode(actual)) => assert_eq!(actual, code, "{}", name),
+                Err(e) => panic!("{}: {:?}", name, e),
+            }
+        }
+    }));
+}
+
+#[test] fn status_line() {
+    each_transport(|name, transport, server| autoreleasepool(|pool| {
+        let future = Request::new(server.url("/status/503"), pool).unwrap()
+            .transport(transport.clone())
+            .perform(pool);
+        let response = kiruna::test::test_await(future, TIMEOUT).unwrap();
+        assert_eq!(response.status(), 503, "{}", name);
+        assert_eq!(response.reason(), "Service Unavailable", "{}", name);
+        //the server only speaks HTTP/1.1, but not every backend can say so
+        assert!(matches!(response.version(), None | Some(Version::Http11)), "{}", name);
+        assert_eq!(response.url(), server.url("/status/503"), "{}", name);
+    }));
+}
+
+#[test] fn redirects() {
+    each_transport(|name, transport, server| autoreleasepool(|pool| {
+        let future = Request::new(server.url("/redirect/3"), pool).unwrap()
+            .transport(transport.clone())
+            .perform(pool);
+        let response = kiruna::test::test_await(future, TIMEOUT).unwrap();
+        assert_eq!(response.check_status().unwrap().as_slice(), b"redirected", "{}", name);
+        assert_eq!(response.url(), server.url("/redirect/0"), "{}", name);
+
+        let future = Request::new(server.url("/redirect/1"), pool).unwrap()
+            .transport(transport)
+            .download(pool);
+        let downloaded = kiruna::test::test_await(future, TIMEOUT).unwrap();
+        assert_eq!(downloaded.status(), 200, "{}", name);
+        assert_eq!(downloaded.reason(), "OK", "{}", name);
+        assert_eq!(downloaded.url(), server.url("/redirect/0"), "{}", name);
+        assert_eq!(downloaded.header("content-length"), Some("10"), "{}", name);
+    }));
+}
+
+#[test] fn download_naming() {
+    each_transport(|name, transport, server| autoreleasepool(|pool| {
+        let future = Request::new(server.url("/large/4096"), pool).unwrap()
+            .transport(transport.clone())
+            .download(pool);
+        let downloaded = kiruna::test::test_await(future, TIMEOUT).unwrap();
+        let path = downloaded.copy_path();
+        assert_eq!(path.file_name().unwrap(), "4096", "{}", name);
+        assert_eq!(std::fs::read(&path).unwrap(), large_body(4096), "{}", name);
+        drop(downloaded);
+        assert!(!path.exists(), "{}: download outlived Downloaded", name);
+
+        let future = Request::new(server.url("/"), pool).unwrap()
+            .transport(transport)
+            .download(pool);
+        let downloaded = kiruna::test::test_await(future, TIMEOUT).unwrap();
+        assert_eq!(downloaded.copy_path().file_name().unwrap(), "requestsr", "{}", name);
+    }));
+}
+
+#[test] fn error_categories() {
+    each_transport(|name, transport, server| autoreleasepool(|pool| {
+        let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let future = Request::new(format!("http://{}/", closed), pool).unwrap()
+            .transport(transport.clone())
+            .perform(pool);
+        let result = kiruna::test::test_await(future, TIMEOUT);
+        assert!(matches!(result, Err(Error::Connect(_))), "{}: {:?}", name, result);
+        let error = result.unwrap_err();
+        assert!(error.is_connect() && error.is_retryable() && !error.is_timeout(), "{}", name);
+
+        let future = Request::new(server.url("/drop"), pool).unwrap()
+            .transport(transport.clone())
+            .perform(pool);
+        let result = kiruna::test::test_await(future, TIMEOUT);
+        assert!(matches!(result, Err(Error::Connect(_))), "{}: {:?}", name, result);
+
+        let future = Request::new(pstr!("http://does-not-exist.invalid/"), pool).unwrap()
+            .transport(transport)
+            .perform(pool);
+        let result = kiruna::test::test_await(future, TIMEOUT);
+        assert!(matches!(result, Err(Error::Dns(_))), "{}: {:?}", name, result);
+        let error = result.unwrap_err();
+        assert!(std::error::Error::source(&error).is_some(), "{}", name);
+        assert!(error.to_string().starts_with("couldn't resolve host"), "{}", name);
+        assert!(!error.is_retryable() && !error.is_connect(), "{}", name);
+    }));
+}
+
+#[test] fn timeouts() {
+    each_transport(|name, transport, server| autoreleasepool(|pool| {
+        let started = Instant::now();
+        let future = Request::new(server.url("/slow/30000"), pool).unwrap()
+            .timeout(Duration::from_millis(300))
+            .transport(transport.clone())
+            .perform(pool);
+        let result = kiruna::test::test_await(future, TIMEOUT);
+        assert!(matches!(&result, Err(e) if e.is_timeout()), "{}: {:?}", name, result);
+        assert!(started.elapsed() < Duration::from_secs(5), "{}", name);
+
+        let future = Request::new(server.url("/slow/100"), pool).unwrap()
+            .timeout(Duration::from_secs(5))
+            .read_idle_timeout(Duration::from_secs(5))
+            .connect_timeout(Duration::from_secs(5))
+            .transport(transport.clone())
+            .perform(pool);
+        let response = kiruna::test::test_await(future, TIMEOUT).unwrap();
+        assert_eq!(response.check_status().unwrap().as_slice(), b"slow", "{}", name);
+
+        //HttpClient has no idle timeout
+        if name == "HttpClient" { return }
+        let future = Request::new(server.url("/slow/30000"), pool).unwrap()
+            .read_idle_timeout(Duration::from_millis(300))
+            .transport(transport)
+            .download(pool);
+        let result = kiruna::test::test_await(future, TIMEOUT);
+        assert!(matches!(&result, Err(Error::Timeout(_))), "{}: {:?}", name, result);
+    }));
+}
+
+struct NoopWake;
+impl Wake for NoopWake {
+    fn wake(self: Arc<Self>) {}
+}
+
+#[test] fn cancel_on_drop() {
+    each_transport(|name, transport, server| autoreleasepool(|pool| {
+        let mut future = Box::pin(Request::new(server.url("/slow/30000"), pool).unwrap()
+            .transport(transport.clone())
+            .perform(pool));
+        let waker = Waker::from(Arc::new(NoopWake));
+        assert!(future.as_mut().poll(&mut Context::from_waker(&waker)).is_pending(), "{}", name);
+        //give the request time to reach the server
+        std::thread::sleep(Duration::from_millis(200));
+        drop(future);
+        let deadline = Instant::now() + TIMEOUT;
+        while server.abandoned() == 0 {
+            assert!(Instant::now() < deadline, "{}: the request outlived its future", name);
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }));
+}
+
+#[test] fn cancel_handle() {
+    each_transport(|name, transport, server| autoreleasepool(|pool| {
+        let (future, handle) = Request::new(server.url("/slow/30000"), pool).unwrap()
+            .transport(transport)
+            .download_cancellable(pool);
+        let started = Instant::now();
+        let canceller = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(200));
+            handle.cancel();
+        });
+        let result = kiruna::test::test_await(future, TIMEOUT);
+        assert!(matches!(result, Err(Error::Cancelled)), "{}: {:?}", name, result);
+        assert!(started.elapsed() < Duration::from_secs(5), "{}", name);
+        canceller.join().unwrap();
+        let deadline = Instant::now() + TIMEOUT;
+        while server.abandoned() == 0 {
+            assert!(Instant::now() < deadline, "{}: the request outlived its cancellation", name);
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }));
+}
@@ -0,0 +1,351 @@
+/*!
+Errors.
+
+[Error] sorts failures into portable categories, so callers can branch on a DNS failure or a timeout the same way on
+every platform.  The backend's own error stays reachable through [std::error::Error::source] as a [PlatformError].
+*/
+use std::fmt::{Debug, Display, Formatter};
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    InvalidURL(String),
+    ///The header field with this name has a name or value that RFC 9110 doesn't allow, such as one containing a line
+    ///break.  See [crate::Request::header].
+    InvalidHeader(String),
+    ///This isn't a method RFC 9110 allows.  See [crate::Method::new].
+    InvalidMethod(String),
+    ///A value couldn't be serialized into a request, because of this problem.  See
+    ///[crate::Request::serialize_query] and [crate::Request::serialize_form].
+    Serialize(String),
+    ///The server responded with a status that isn't success.  See [crate::Downloaded::check_status].
+    StatusCode(crate::StatusCode),
+    ///The server answered 429 Too Many Requests, or 503 Service Unavailable with a delay, and asked us to wait
+    ///`retry_after` before trying again.  See [crate::Response::retry_after].
+    RateLimited { status: crate::StatusCode, retry_after: Option<std::time::Duration> },
+    ///The request, or some part of it, took too long.
+    Timeout(PlatformError),
+    ///The host name couldn't be resolved.
+    Dns(PlatformError),
+    ///A connection to the server couldn't be established, or was lost.
+    Connect(PlatformError),
+    ///The TLS handshake failed, for example because the certificate is not trusted.
+    Tls(PlatformError),
+    ///The request was cancelled before it finished.
+    Cancelled,
+    ///The server redirected more times than the backend allows.
+    TooManyRedirects(PlatformError),
+    ///The response was malformed or cut short.
+    BodyRead(PlatformError),
+    ///A local I/O error, for example writing a download to disk.
+    Io(std::io::Error),
+    ///A mock or replaying transport had nothing to answer this request (`METHOD URL`) with.
+    Unmatched(String),
+    ///Any other failure in the platform's HTTP stack.
+    Platform(PlatformError),
+    ///The backend can't do what was asked, described here.  See [crate::ClientBuilder::build].
+    Unsupported(String),
+}
+impl Error {
+    ///Whether the same request may succeed if tried again.
+    ///
+    /// True for timeouts, connection failures, responses cut short, [Error::RateLimited], and a
+    /// [Error::StatusCode] that [crate::Response::is_retryable_status] accepts.  DNS and TLS failures, cancellation, and errors in the request
+    /// itself are not retryable.
+    ///
+    /// This is the same on every backend.  It doesn't know whether the request is safe to repeat; a `POST` that
+    /// failed with [Error::BodyRead] may already have taken effect.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::Timeout(_) | Error::Connect(_) | Error::BodyRead(_) => true,
+            Error::StatusCode(code) => code.is_retryable(),
+            Error::RateLimited { .. } => true,
+            _ => false,
+        }
+    }
+    ///Whether this is an [Error::Timeout].
+    pub fn is_timeout(&self) -> bool {
+        matches!(self, Error::Timeout(_))
+    }
+    ///Whether a connection couldn't be established or was lost, which is [Error::Connect].
+    ///
+    /// DNS failures are [Error::Dns] and TLS failures are [Error::Tls]; neither counts.
+    pub fn is_connect(&self) -> bool {
+        matches!(self, Error::Connect(_))
+    }
+    ///The backend's error, for the variants that have one.
+    pub fn platform(&self) -> Option<&PlatformError> {
+        match self {
+            Error::Timeout(e) | Error::Dns(e) | Error::Connect(e) | Error::Tls(e) |
+            Error::TooManyRedirects(e) | Error::BodyRead(e) | Error::Platform(e) => Some(e),
+            _ => None,
+        }
+    }
+    fn platform_mut(&mut self) -> Option<&mut PlatformError> {
+        match self {
+            Error::Timeout(e) | Error::Dns(e) | Error::Connect(e) | Error::Tls(e) |
+            Error::TooManyRedirects(e) | Error::BodyRead(e) | Error::Platform(e) => Some(e),
+            _ => None,
+        }
+    }
+    ///How many times the request was sent before failing, under a [crate::RetryPolicy].
+    ///
+    /// A retried error keeps the variant of the last attempt's error, and the count is kept on its
+    /// [PlatformError], so only the variants that have one ([Error::platform]) know it.  The others always report 1,
+    /// however many times they were retried.
+    ///
+    /// Retried statuses, such as 503 or 429, aren't errors from [crate::Request::perform] or
+    /// [crate::Request::download]: the last response comes back, and [crate::Response::attempts] has the count.  The
+    /// [Error::StatusCode] or [Error::RateLimited] that [crate::Downloaded::check_status] makes from it reports 1.  So
+    /// does one returned by a custom [crate::transport::Transport], or any other variant retried under
+    /// [crate::RetryPolicy::retry_errors].
+    pub fn attempts(&self) -> u32 {
+        self.platform().map_or(1, PlatformError::attempts)
+    }
+    ///Records that the request was sent `attempts` times, if the variant has somewhere to keep it.
+    pub(crate) fn set_attempts(&mut self, attempts: u32) {
+        if let Some(e) = self.platform_mut() {
+            e.attempts = attempts;
+        }
+    }
+}
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::InvalidURL(url) => write!(f, "invalid URL: {}", url),
+            Error::InvalidHeader(name) => write!(f, "invalid header field {:?}", name),
+            Error::InvalidMethod(method) => write!(f, "invalid method {:?}", method),
+            Error::Serialize(problem) => write!(f, "couldn't serialize: {}", problem),
+            Error::StatusCode(code) => write!(f, "server responded with status {}", code.as_u16()),
+            Error::RateLimited { status, retry_after: Some(delay) } => write!(f, "rate limited with status {}; retry after {:?}", status.as_u16(), delay),
+            Error::RateLimited { status, retry_after: None } => write!(f, "rate limited with status {}", status.as_u16()),
+            Error::Timeout(e) => write!(f, "request timed out: {}", e),
+            Error::Dns(e) => write!(f, "couldn't resolve host: {}", e),
+            Error::Connect(e) => write!(f, "couldn't connect: {}", e),
+            Error::Tls(e) => write!(f, "TLS error: {}", e),
+            Error::Cancelled => f.write_str("request was cancelled"),
+            Error::TooManyRedirects(e) => write!(f, "too many redirects: {}", e),
+            Error::BodyRead(e) => write!(f, "couldn't read response: {}", e),
+            Error::Io(e) => write!(f, "I/O error: {}", e),
+            Error::Unmatched(request) => write!(f, "nothing to answer {} with", request),
+            Error::Platform(e) => Display::fmt(e, f),
+            Error::Unsupported(what) => write!(f, "not supported by this backend: {}", what),
+        }
+    }
+}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Timeout(e) | Error::Dns(e) | Error::Connect(e) | Error::Tls(e) |
+            Error::TooManyRedirects(e) | Error::BodyRead(e) | Error::Platform(e) => Some(e),
+            Error::Io(e) => Some(e),
+            Error::InvalidURL(_) | Error::InvalidHeader(_) | Error::InvalidMethod(_) | Error::Serialize(_) | Error::StatusCode(_) | Error::RateLimited { .. } | Error::Cancelled | Error::Unmatched(_) | Error::Unsupported(_) => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Cause {
+    Message(String),
+    Io(std::io::Error),
+    #[cfg(target_os = "macos")]
+    ///The error, with its localized description, domain and code for display
+    Pcore(pcore::error::Error, String),
+    #[cfg(target_os = "windows")]
+    Windows(::windows::core::Error),
+    #[cfg(target_os = "windows")]
+    WinFuture(winfuture::Error),
+    #[cfg(target_os = "linux")]
+    Curl(curl::Error),
+}
+
+///The error reported by the backend that carried out a request.
+#[derive(Debug)]
+pub struct PlatformError {
+    cause: Cause,
+    attempts: u32,
+}
+impl PlatformError {
+    fn new(cause: Cause) -> PlatformError {
+        PlatformError { cause, attempts: 1 }
+    }
+    ///An error with only a description, for failures detected by this crate or a custom transport.
+    pub fn message(message: &str) -> PlatformError {
+        PlatformError::new(Cause::Message(message.to_owned()))
+    }
+    ///The `NSError` from `NSURLSession`.
+    #[cfg(target_os = "macos")]
+    pub fn as_pcore(&self) -> Option<&pcore::error::Error> {
+        match &self.cause { Cause::Pcore(e, _) => Some(e), _ => None }
+    }
+    ///The error from WinRT's `HttpClient`.
+    #[cfg(target_os = "windows")]
+    pub fn as_windows(&self) -> Option<&::windows::core::Error> {
+        match &self.cause { Cause::Windows(e) => Some(e), _ => None }
+    }
+    ///The error from libcurl.
+    #[cfg(target_os = "linux")]
+    pub fn as_curl(&self) -> Option<&curl::Error> {
+        match &self.cause { Cause::Curl(e) => Some(e), _ => None }
+    }
+    ///How many times the request was sent, the last time failing with this error.  See [Error::attempts].
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+}
+impl Display for PlatformError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.cause {
+            Cause::Message(message) => f.write_str(message),
+            Cause::Io(e) => Display::fmt(e, f),
+            #[cfg(target_os = "macos")]
+            Cause::Pcore(_, description) => f.write_str(description),
+            #[cfg(target_os = "windows")]
+            Cause::Windows(e) => Display::fmt(e, f),
+            #[cfg(target_os = "windows")]
+            //winfuture's error has no description of its own; the details are in the Debug output
+            Cause::WinFuture(_) => f.write_str("the WinRT operation failed"),
+            #[cfg(target_os = "linux")]
+            Cause::Curl(e) => Display::fmt(e, f),
+        }?;
+        if self.attempts > 1 {
+            write!(f, " (after {} attempts)", self.attempts)?;
+        }
+        Ok(())
+    }
+}
+impl std::error::Error for PlatformError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match &self.cause {
+            Cause::Io(e) => Some(e),
+            #[cfg(target_os = "windows")]
+            Cause::Windows(e) => Some(e),
+            #[cfg(target_os = "linux")]
+            Cause::Curl(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+impl From<std::io::Error> for PlatformError {
+    fn from(e: std::io::Error) -> Self {
+        PlatformError::new(Cause::Io(e))
+    }
+}
+#[cfg(target_os = "macos")]
+impl PlatformError {
+    ///`error`, shown as `description`, which should be the `NSError`'s localized description, domain and code.
+    pub(crate) fn from_pcore(error: pcore::error::Error, description: String) -> Self {
+        PlatformError::new(Cause::Pcore(error, description))
+    }
+}
+#[cfg(target_os = "linux")]
+impl From<curl::Error> for PlatformError {
+    fn from(e: curl::Error) -> Self {
+        PlatformError::new(Cause::Curl(e))
+    }
+}
+
+#[cfg(target_os = "windows")]
+impl From<::windows::core::Error> for Error {
+    ///Sorts WinINet errors, which `HttpClient` reports as `HRESULT_FROM_WIN32` codes.
+    fn from(e: ::windows::core::Error) -> Self {
+        const E_ABORT: u32 = 0x8000_4004;
+        const ERROR_CANCELLED: u32 = 0x8007_04C7;
+        let code = e.code().0 as u32;
+        let wininet = if code & 0xFFFF_0000 == 0x8007_0000 { code & 0xFFFF } else { 0 };
+        let e = PlatformError::new(Cause::Windows(e));
+        match (code, wininet) {
+            (E_ABORT | ERROR_CANCELLED, _) => Error::Cancelled,
+            //ERROR_INTERNET_TIMEOUT
+            (_, 12002) => Error::Timeout(e),
+            //ERROR_INTERNET_NAME_NOT_RESOLVED
+            (_, 12007) => Error::Dns(e),
+            //CANNOT_CONNECT, CONNECTION_ABORTED, CONNECTION_RESET
+            (_, 12029 | 12030 | 12031) => Error::Connect(e),
+            //SEC_CERT_DATE_INVALID, SEC_CERT_CN_INVALID, INVALID_CA, SEC_CERT_ERRORS, SEC_CERT_NO_REV, SEC_CERT_REV_FAILED,
+            //SECURITY_CHANNEL_ERROR, SEC_CERT_REVOKED, SEC_INVALID_CERT
+            (_, 12037 | 12038 | 12045 | 12055 | 12056 | 12057 | 12157 | 12170 | 12169) => Error::Tls(e),
+            //ERROR_HTTP_REDIRECT_FAILED
+            (_, 12156) => Error::TooManyRedirects(e),
+            //ERROR_HTTP_INVALID_SERVER_RESPONSE, ERROR_INTERNET_DECODING_FAILED
+            (_, 12152 | 12175) => Error::BodyRead(e),
+            _ => Error::Platform(e),
+        }
+    }
+}
+#[cfg(target_os = "windows")]
+impl From<winfuture::Error> for Error {
+    fn from(e: winfuture::Error) -> Self {
+        Error::Platform(PlatformError::new(Cause::WinFuture(e)))
+    }
+}
+#[cfg(target_os = "linux")]
+impl From<curl::Error> for Error {
+    fn from(e: curl::Error) -> Self {
+        if e.is_aborted_by_callback() {
+            return Error::Cancelled;
+        }
+        let category: fn(PlatformError) -> Error = if e.is_operation_timedout() {
+            Error::Timeout
+        }
+        else if e.is_couldnt_resolve_host() || e.is_couldnt_resolve_proxy() {
+            Error::Dns
+        }
+        else if e.is_couldnt_connect() || e.is_send_error() || e.is_got_nothing() {
+            Error::Connect
+        }
+        else if e.is_ssl_connect_error() || e.is_peer_failed_verification() || e.is_ssl_certproblem() ||
+            e.is_ssl_cipher() || e.is_ssl_cacert() || e.is_ssl_cacert_badfile() {
+            Error::Tls
+        }
+        else if e.is_too_many_redirects() {
+            Error::TooManyRedirects
+        }
+        else if e.is_recv_error() || e.is_partial_file() || e.is_bad_content_encoding() ||
+            e.is_write_error() {
+            Error::BodyRead
+        }
+        else {
+            Error::Platform
+        };
+        category(PlatformError::new(Cause::Curl(e)))
+    }
+}
+
+#[cfg(test)] mod test {
+    use super::{Error, PlatformError};
+    use std::error::Error as _;
+
+    #[test] fn display() {
+        let e = Error::Dns(PlatformError::message("no such host"));
+        assert_eq!(e.to_string(), "couldn't resolve host: no such host");
+        assert_eq!(e.source().unwrap().to_string(), "no such host");
+        let io = Error::Io(std::io::ErrorKind::NotFound.into());
+        assert!(io.source().unwrap().downcast_ref::<std::io::Error>().is_some());
+        assert_eq!(Error::StatusCode(crate::StatusCode::NOT_FOUND).to_string(), "server responded with status 404");
+        assert!(Error::Cancelled.source().is_none());
+        let limited = Error::RateLimited { status: crate::StatusCode::TOO_MANY_REQUESTS, retry_after: Some(std::time::Duration::from_secs(30)) };
+        assert_eq!(limited.to_string(), "rate limited with status 429; retry after 30s");
+        assert!(limited.is_retryable());
+    }
+
+    #[test] fn retryable() {
+        let e = || PlatformError::message("");
+        assert!(Error::Timeout(e()).is_retryable() && Error::Timeout(e()).is_timeout());
+        assert!(Error::Connect(e()).is_retryable() && Error::Connect(e()).is_connect());
+        assert!(Error::BodyRead(e()).is_retryable());
+        for code in [408, 425, 429, 500, 503, 599].map(|c| crate::StatusCode::new(c).unwrap()) {
+            assert!(Error::StatusCode(code).is_retryable(), "{}", code);
+            assert!(crate::Response::new(code, Vec::new().into()).is_retryable_status(), "{}", code);
+        }
+        for code in [200, 301, 400, 404, 409, 426, 600].map(|c| crate::StatusCode::new(c).unwrap()) {
+            assert!(!Error::StatusCode(code).is_retryable(), "{}", code);
+            assert!(!crate::Response::new(code, Vec::new().into()).is_retryable_status(), "{}", code);
+        }
+        assert!(!Error::Dns(e()).is_retryable() && !Error::Dns(e()).is_connect());
+        assert!(!Error::Tls(e()).is_retryable());
+        assert!(!Error::Cancelled.is_retryable());
+        assert!(!Error::InvalidURL(String::new()).is_retryable());
+        assert!(!Error::Platform(e()).is_timeout());
+    }
+}
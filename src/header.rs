@@ -0,0 +1,193 @@
+/*!
+Header fields.
+*/
+use std::fmt::{Debug, Formatter};
+use crate::Error;
+
+///Whether `name` is a field name, which RFC 9110 defines as a `token`.
+pub(crate) fn is_token(name: &str) -> bool {
+    !name.is_empty() && name.bytes().all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+///Whether `value` is an RFC 9110 `field-value`: visible characters, with spaces and tabs only between them.
+///
+/// Bytes outside ASCII are allowed as `obs-text`.  Control characters, including CR and LF, are not.
+fn is_field_value(value: &str) -> bool {
+    value.bytes().all(|b| b == b' ' || b == b'\t' || (b > 0x20 && b != 0x7f)) &&
+        !value.starts_with([' ', '\t']) && !value.ends_with([' ', '\t'])
+}
+
+///Checks a field against RFC 9110, so user-supplied data can't inject fields of its own.
+pub(crate) fn validate(name: &str, value: &str) -> Result<(),Error> {
+    if is_token(name) && is_field_value(value) {
+        Ok(())
+    }
+    else {
+        Err(Error::InvalidHeader(name.to_owned()))
+    }
+}
+
+///Header fields, looked up case-insensitively.
+///
+/// A name may appear more than once (`Set-Cookie`, for example).  Fields keep the order they were added in.
+#[derive(Clone,Default,PartialEq,Eq)]
+pub struct HeaderMap {
+    fields: Vec<(String, String)>,
+}
+impl HeaderMap {
+    pub fn new() -> Self {
+        HeaderMap::default()
+    }
+    ///Adds a field, keeping any existing fields with the same name.
+    pub fn append(&mut self, name: &str, value: &str) {
+        self.fields.push((name.to_owned(), value.to_owned()));
+    }
+    ///Sets the only value for `name`, replacing any existing fields with that name.
+    ///
+    /// The field keeps the position of the first one it replaces.
+    pub fn insert(&mut self, name: &str, value: &str) {
+        let mut replaced = false;
+        self.fields.retain_mut(|f| {
+            if !f.0.eq_ignore_ascii_case(name) {
+                return true;
+            }
+            if replaced {
+                return false;
+            }
+            replaced = true;
+            *f = (name.to_owned(), value.to_owned());
+            true
+        });
+        if !replaced {
+            self.append(name, value);
+        }
+    }
+    ///Removes every field named `name`, returning whether there were any.
+    pub fn remove(&mut self, name: &str) -> bool {
+        let len = self.fields.len();
+        self.fields.retain(|f| !f.0.eq_ignore_ascii_case(name));
+        self.fields.len() != len
+    }
+    ///The first value for `name`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.fields.iter().find(|f| f.0.eq_ignore_ascii_case(name)).map(|f| f.1.as_str())
+    }
+    ///Every value for `name`, in order.
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item=&'a str> + 'a {
+        self.fields.iter().filter(move |f| f.0.eq_ignore_ascii_case(name)).map(|f| f.1.as_str())
+    }
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+    ///Every field as `(name, value)`, in order.  Names are as received.
+    pub fn iter(&self) -> impl Iterator<Item=(&str, &str)> {
+        self.fields.iter().map(|f| (f.0.as_str(), f.1.as_str()))
+    }
+    ///The number of fields, counting each value of a repeated name.
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+    ///Checks every field with [validate].
+    pub(crate) fn validate(&self) -> Result<(),Error> {
+        self.iter().try_for_each(|(name, value)| validate(name, value))
+    }
+    ///One field per name, in the order names first appear, with repeated values joined by `, ` as RFC 9110 allows.
+    ///
+    /// `Cookie`, which HTTP/1.1 doesn't allow to be repeated, is joined by `; `.  Not every platform can send a name
+    /// twice, so this is how every backend sends request headers.
+    pub(crate) fn combined(&self) -> Vec<(&str, String)> {
+        let mut combined: Vec<(&str, String)> = Vec::new();
+        for (name, value) in self.iter() {
+            match combined.iter_mut().find(|c| c.0.eq_ignore_ascii_case(name)) {
+                Some(existing) => {
+                    existing.1.push_str(if name.eq_ignore_ascii_case("cookie") { "; " } else { ", " });
+                    existing.1.push_str(value);
+                }
+                None => combined.push((name, value.to_owned())),
+            }
+        }
+        combined
+    }
+}
+impl Debug for HeaderMap {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+impl<N: AsRef<str>, V: AsRef<str>> FromIterator<(N, V)> for HeaderMap {
+    fn from_iter<T: IntoIterator<Item=(N, V)>>(iter: T) -> Self {
+        let mut map = HeaderMap::new();
+        for (name, value) in iter {
+            map.append(name.as_ref(), value.as_ref());
+        }
+        map
+    }
+}
+
+#[cfg(test)] mod test {
+    use super::{HeaderMap, validate};
+    use crate::{Request, Error, StatusCode};
+    use crate::mock::{MockTransport, Matcher};
+    use pcore::pstr;
+    use pcore::release_pool::autoreleasepool;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test] fn lookup() {
+        let map: HeaderMap = [("Content-Type", "text/plain"), ("Set-Cookie", "a=1"), ("set-cookie", "b=2")].into_iter().collect();
+        assert_eq!(map.get("content-type"), Some("text/plain"));
+        assert_eq!(map.get("SET-COOKIE"), Some("a=1"));
+        assert_eq!(map.get_all("Set-Cookie").collect::<Vec<_>>(), ["a=1", "b=2"]);
+        assert!(!map.contains("ETag"));
+        assert_eq!(map.len(), 3);
+        assert_eq!(map.iter().nth(2), Some(("set-cookie", "b=2")));
+    }
+
+    #[test] fn insert_and_remove() {
+        let mut map: HeaderMap = [("Accept", "a"), ("X-Id", "1"), ("accept", "b")].into_iter().collect();
+        map.insert("ACCEPT", "c");
+        assert_eq!(map.iter().collect::<Vec<_>>(), [("ACCEPT", "c"), ("X-Id", "1")]);
+        map.insert("Cookie", "a=1");
+        assert_eq!(map.len(), 3);
+        assert!(map.remove("x-id"));
+        assert!(!map.remove("x-id"));
+        assert_eq!(map.iter().collect::<Vec<_>>(), [("ACCEPT", "c"), ("Cookie", "a=1")]);
+    }
+
+    #[test] fn combined() {
+        let map: HeaderMap = [("Accept", "a"), ("Cookie", "a=1"), ("accept", "b"), ("cookie", "b=2")].into_iter().collect();
+        assert_eq!(map.combined(), [("Accept", "a, b".to_owned()), ("Cookie", "a=1; b=2".to_owned())]);
+    }
+
+    #[test] fn validation() {
+        for (name, value) in [("X-Token", "a\tb c"), ("x.y_z~1", ""), ("Accept", "caf\u{e9}"), ("ETag", "\"v1\"")] {
+            assert!(validate(name, value).is_ok(), "{}: {}", name, value);
+        }
+        for (name, value) in [("", "a"), ("X Y", "a"), ("X:Y", "a"), ("X-\u{e9}", "a"), ("X-A", "a\r\nX-B: b"), ("X-A", "a\nb"),
+                              ("X-A", "a\0"), ("X-A", " a"), ("X-A", "a\t"), ("X-A", "\u{7f}")] {
+            assert!(matches!(validate(name, value), Err(Error::InvalidHeader(n)) if n == name), "{:?}: {:?}", name, value);
+        }
+    }
+
+    #[test] fn invalid_request_header() {
+        autoreleasepool(|pool| {
+            let mock = Arc::new(MockTransport::new());
+            mock.on(Matcher::any(), StatusCode::OK, b"");
+            let request = Request::new(pstr!("https://example.com/"), pool).unwrap()
+                .header(pstr!("X-Name"), Some("evil\r\nX-Injected: 1"), pool)
+                .append_header(pstr!("X-Other"), "\n", pool)
+                .header(pstr!("Accept"), Some("text/plain"), pool)
+                .transport(mock.clone());
+            //the description never holds the refused fields, so a transport handed it directly can't send them
+            let headers = request.description().headers();
+            assert!(!headers.contains("X-Name") && !headers.contains("X-Other") && headers.contains("Accept"));
+            let result = kiruna::test::test_await(request.perform(pool), Duration::from_secs(1));
+            //the first refused field is reported
+            assert!(matches!(result, Err(Error::InvalidHeader(name)) if name == "X-Name"));
+            mock.assert_calls(&Matcher::any(), 0);
+        });
+    }
+}
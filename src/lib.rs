@@ -17,9 +17,26 @@ Disadvantages:
 Currently supported:
 * macOS - uses `NSURLSession` as backend
 * windows - uses `HTTPClient` as backend
+* linux - uses libcurl as backend
+* elsewhere, the `std-backend` feature provides a plain HTTP/1.1 client built on `std::net`
 
 */
-use std::fmt::{Formatter, Debug};
+pub mod request;
+pub mod response;
+pub mod method;
+pub mod status;
+pub mod url;
+pub mod header;
+pub mod body;
+pub mod multipart;
+pub mod error;
+pub mod retry;
+pub mod cancel;
+pub mod client;
+pub mod transport;
+pub mod mock;
+pub mod cassette;
+mod timer;
 
 #[cfg(target_os = "macos")]
 mod macos;
@@ -27,49 +44,37 @@ mod macos;
 #[cfg(target_os = "windows")]
 mod windows;
 
-#[cfg(target_os = "macos")]
-pub use macos::request::Request;
+#[cfg(target_os = "linux")]
+mod linux;
 
-#[cfg(target_os = "windows")]
-pub use self::windows::request::Request;
+#[cfg(feature = "std-backend")]
+mod std_backend;
 
-#[cfg(target_os = "windows")]
-#[doc(hidden)]
-pub use wchar::wchz as __wchz;
+#[cfg(any(target_os = "linux", feature = "std-backend"))]
+mod blocking;
 
-#[cfg(target_os = "macos")]
-pub use macos::response::{Response,Downloaded};
+#[cfg(all(test, feature = "std-backend"))]
+mod test_support;
 
+#[cfg(any(test, feature = "test-server"))]
+pub mod test_server;
 
+#[cfg(test)]
+mod conformance;
 
-#[derive(Debug)]
-#[non_exhaustive]
-pub enum Error {
-    InvalidURL(String),
-    #[cfg(target_os = "windows")]
-    PlatformError(::windows::core::Error),
-    PcoreError(pcore::error::Error),
-    StatusCode(u16),
-    #[cfg(target_os = "windows")]
-    WinFuture(winfuture::Error),
-}
-#[cfg(target_os = "windows")]
-impl From<::windows::core::Error> for Error {
-    fn from(e: ::windows::core::Error) -> Self {
-        Error::PlatformError(e)
-    }
-}
 #[cfg(target_os = "windows")]
-impl From<winfuture::Error> for Error {
-    fn from(e: winfuture::Error) -> Self {
-        Error::WinFuture(e)
-    }
-}
-impl std::fmt::Display for Error {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        f.write_fmt(format_args!("{:?}",self))
-    }
-}
-impl std::error::Error for Error {}
-
+#[doc(hidden)]
+pub use wchar::wchz as __wchz;
 
+pub use request::{Request, DEFAULT_USER_AGENT, FORM_CONTENT_TYPE};
+pub use response::{Response,Downloaded,Data,Version};
+pub use method::Method;
+pub use status::StatusCode;
+pub use url::{Url, IntoUrl};
+pub use header::HeaderMap;
+pub use body::{Body, BodyReader};
+pub use multipart::{Multipart, Part};
+pub use error::{Error, PlatformError};
+pub use retry::RetryPolicy;
+pub use cancel::CancelHandle;
+pub use client::{Client, ClientBuilder};
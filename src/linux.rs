@@ -0,0 +1,2 @@
+mod request;
+pub use request::CurlTransport;
\ No newline at end of file
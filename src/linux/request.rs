@@ -0,0 +1,404 @@
+use crate::{Error, Response, Downloaded, Method, StatusCode, Body, BodyReader};
+use crate::request::RequestDescription;
+use crate::transport::{Transport, BoxFuture};
+use crate::blocking::Cancellation;
+use crate::header::HeaderMap;
+use crate::response::{Metadata, Version};
+use crate::client::SessionConfig;
+use curl::easy::{Easy2, Handler, List, ReadError, SeekResult, WriteError};
+use std::fs::File;
+use std::future::Future;
+use std::io::{Read, SeekFrom, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tempfile::tempdir;
+
+///Parses header lines as curl delivers them.
+///
+/// curl reports the headers of every response it sees, including redirects and `100 Continue`, so we start over at
+/// each status line and end up with the final response's head.
+#[derive(Default)]
+struct HeaderLines {
+    version: Option<Version>,
+    reason: Option<String>,
+    headers: HeaderMap,
+}
+impl HeaderLines {
+    fn line(&mut self, line: &[u8]) {
+        let line = String::from_utf8_lossy(line);
+        let line = line.trim_end();
+        if line.starts_with("HTTP/") {
+            let mut parts = line.splitn(3, ' ');
+            *self = HeaderLines {
+                version: parts.next().and_then(Version::parse),
+                //HTTP/2 has no reason phrase
+                reason: parts.nth(1).filter(|r| !r.is_empty()).map(|r| r.to_owned()),
+                headers: HeaderMap::new(),
+            };
+        }
+        else if let Some((name, value)) = line.split_once(':') {
+            self.headers.append(name.trim(), value.trim());
+        }
+    }
+    fn metadata<H>(&mut self, handle: &mut Easy2<H>) -> Result<Metadata,Error> {
+        Ok(Metadata {
+            code: StatusCode::from_platform(handle.response_code()? as u16),
+            reason: self.reason.take(),
+            version: self.version,
+            url: handle.effective_url()?.unwrap_or_default().to_owned(),
+            headers: std::mem::take(&mut self.headers),
+            ..Metadata::default()
+        })
+    }
+}
+
+///Enforces [RequestDescription::read_idle_timeout] from the progress callback.
+///
+/// libcurl's own low-speed limit counts in whole seconds.
+struct IdleTimer {
+    limit: Option<Duration>,
+    last_progress: Instant,
+    transferred: f64,
+    expired: bool,
+}
+impl IdleTimer {
+    fn new(limit: Option<Duration>) -> Self {
+        IdleTimer { limit, last_progress: Instant::now(), transferred: 0.0, expired: false }
+    }
+    ///Whether to keep going, given the bytes transferred so far.
+    fn progress(&mut self, transferred: f64) -> bool {
+        if transferred != self.transferred {
+            self.transferred = transferred;
+            self.last_progress = Instant::now();
+        }
+        self.expired = self.limit.is_some_and(|limit| self.last_progress.elapsed() > limit);
+        !self.expired
+    }
+    ///Converts the error from a transfer that this timer may have aborted.
+    fn error(&self, e: curl::Error) -> Error {
+        if self.expired { Error::Timeout(e.into()) } else { e.into() }
+    }
+}
+
+///Where the response body goes.
+enum Sink {
+    Memory(Vec<u8>),
+    ///A short write makes curl fail the transfer with a write error.
+    File(File),
+}
+
+///A body streamed from files, which libcurl pulls from [Transfer::read].
+struct Upload {
+    body: Body,
+    reader: BodyReader,
+    ///Why reading failed, which libcurl only reports as an aborted transfer
+    error: Option<std::io::Error>,
+}
+
+///Receives one transfer.  Handles are reused, so this is replaced for each request.
+struct Transfer {
+    sink: Sink,
+    headers: HeaderLines,
+    idle: IdleTimer,
+    cancellation: Option<Cancellation>,
+    upload: Option<Upload>,
+    ///Why writing the download failed, which libcurl only reports as a write error
+    write_error: Option<std::io::Error>,
+}
+impl Transfer {
+    fn new(sink: Sink, request: &RequestDescription, cancellation: Cancellation) -> Self {
+        //bodies in memory are copied to libcurl up front
+        let upload = request.body.as_ref().filter(|b| b.as_bytes().is_none())
+            .map(|body| Upload { body: body.clone(), reader: body.reader(), error: None });
+        Transfer { sink, headers: HeaderLines::default(), idle: IdleTimer::new(request.timeouts.read_idle), cancellation: Some(cancellation), upload, write_error: None }
+    }
+    ///Converts the error from a transfer that this may have aborted.
+    ///
+    /// Local file errors are [Error::Io], rather than the network errors libcurl reports them as.
+    fn error(&mut self, e: curl::Error) -> Error {
+        match self.upload.as_mut().and_then(|u| u.error.take()).or_else(|| self.write_error.take()) {
+            Some(e) => Error::Io(e),
+            None => self.idle.error(e),
+        }
+    }
+}
+impl Handler for Transfer {
+    fn write(&mut self, data: &[u8]) -> Result<usize, WriteError> {
+        match &mut self.sink {
+            Sink::Memory(buffer) => buffer.extend_from_slice(data),
+            Sink::File(file) => {
+                if let Err(e) = file.write_all(data) {
+                    self.write_error = Some(e);
+                    return Ok(0);
+                }
+            }
+        }
+        Ok(data.len())
+    }
+    fn header(&mut self, data: &[u8]) -> bool {
+        self.headers.line(data);
+        true
+    }
+    fn read(&mut self, data: &mut [u8]) -> Result<usize, ReadError> {
+        let Some(upload) = &mut self.upload else {
+            return Ok(0);
+        };
+        upload.reader.read(data).map_err(|e| {
+            upload.error = Some(e);
+            ReadError::Abort
+        })
+    }
+    fn seek(&mut self, whence: SeekFrom) -> SeekResult {
+        //libcurl rewinds to send the body again, as after a 307 redirect
+        match (&mut self.upload, whence) {
+            (Some(upload), SeekFrom::Start(0)) => {
+                upload.reader = upload.body.reader();
+                SeekResult::Ok
+            }
+            _ => SeekResult::CantSeek,
+        }
+    }
+    fn progress(&mut self, _dltotal: f64, dlnow: f64, _ultotal: f64, ulnow: f64) -> bool {
+        //returning false aborts the transfer
+        !self.cancellation.as_ref().is_some_and(|c| c.is_cancelled()) && self.idle.progress(dlnow + ulnow)
+    }
+}
+
+///Maximum number of idle handles kept around.
+const MAX_IDLE: usize = 16;
+
+///A pool of curl handles, each with its own connection cache, and the options that go with them.
+struct Session {
+    config: SessionConfig,
+    idle: Mutex<Vec<Easy2<Transfer>>>,
+}
+impl Session {
+    ///Runs `transfer` on a pooled handle, then returns the handle to the pool.
+    fn perform(&self, request: RequestDescription, transfer: Transfer) -> Result<(Transfer, Metadata),Error> {
+        let pooled = self.idle.lock().unwrap().pop();
+        let mut handle = match pooled {
+            Some(mut handle) => {
+                //reset the options, but not the connections
+                handle.reset();
+                *handle.get_mut() = transfer;
+                handle
+            }
+            None => Easy2::new(transfer),
+        };
+        configure(&self.config, request, &mut handle)?;
+        handle.perform().map_err(|e| handle.get_mut().error(e))?;
+        let mut lines = std::mem::take(&mut handle.get_mut().headers);
+        let metadata = lines.metadata(&mut handle)?;
+        let transfer = std::mem::replace(handle.get_mut(), Transfer {
+            sink: Sink::Memory(Vec::new()),
+            headers: HeaderLines::default(),
+            idle: IdleTimer::new(None),
+            cancellation: None,
+            upload: None,
+            write_error: None,
+        });
+        let mut idle = self.idle.lock().unwrap();
+        if idle.len() < MAX_IDLE {
+            idle.push(handle);
+        }
+        Ok((transfer, metadata))
+    }
+}
+
+///Applies the session's and the builder's options to a curl handle.
+fn configure<H: Handler>(config: &SessionConfig, request: RequestDescription, handle: &mut Easy2<H>) -> Result<(),Error> {
+    handle.url(request.url.as_str()).map_err(|_| Error::InvalidURL(request.url.to_string()))?;
+    //match NSURLSession and HttpClient, which follow redirects by default
+    handle.follow_location(config.follow_redirects)?;
+    if let Some(proxy) = &config.proxy {
+        handle.proxy(proxy).map_err(|_| Error::InvalidURL(proxy.clone()))?;
+    }
+    if config.accept_invalid_certs {
+        handle.ssl_verify_peer(false)?;
+        handle.ssl_verify_host(false)?;
+    }
+    handle.progress(true)?;
+    if let Some(timeout) = request.timeouts.connect {
+        handle.connect_timeout(timeout)?;
+    }
+    match &request.method {
+        Method::Get if request.body.is_none() => {handle.get(true)?;}
+        Method::Head => {handle.nobody(true)?;}
+        Method::Post => {
+            handle.post(true)?;
+            handle.post_field_size(0)?;
+        }
+        //libcurl sends the method verbatim, along with any body
+        other => {handle.custom_request(other.as_str())?;}
+    }
+    match request.body.as_ref().map(|b| (b, b.as_bytes())) {
+        None => {}
+        Some((_, Some(bytes))) => {handle.post_fields_copy(bytes)?;}
+        //streamed through Transfer::read
+        Some((body, None)) => {
+            handle.post(true)?;
+            handle.post_field_size(body.len())?;
+        }
+    }
+    let mut list = List::new();
+    //libcurl sends `Expect: 100-continue` for larger bodies, which the other backends don't
+    list.append("Expect:")?;
+    for (name, value) in request.headers.combined() {
+        list.append(&format!("{}: {}", name, value))?;
+    }
+    handle.http_headers(list)?;
+    Ok(())
+}
+
+fn perform(session: Arc<Session>, request: RequestDescription) -> impl Future<Output=Result<Response,Error>> {
+    crate::blocking::spawn(move |cancellation| {
+        let transfer = Transfer::new(Sink::Memory(Vec::new()), &request, cancellation);
+        let (transfer, metadata) = session.perform(request, transfer)?;
+        let Sink::Memory(data) = transfer.sink else { unreachable!() };
+        Ok(Response::from_metadata(metadata, data.into()))
+    })
+}
+
+///Downloads the request into a file.
+///
+/// The file will be located in a temporary directory and will be deleted when the return value is dropped.
+fn download(session: Arc<Session>, request: RequestDescription) -> impl Future<Output=Result<Downloaded,Error>> {
+    crate::blocking::spawn(move |cancellation| {
+        let dir = tempdir().map_err(Error::Io)?;
+        let path = dir.path().join(&request.file_name);
+        let file = File::create(&path).map_err(Error::Io)?;
+        let transfer = Transfer::new(Sink::File(file), &request, cancellation);
+        let (transfer, metadata) = session.perform(request, transfer)?;
+        let Sink::File(mut file) = transfer.sink else { unreachable!() };
+        file.flush().map_err(Error::Io)?;
+        Ok(Downloaded::new(dir, path, metadata))
+    })
+}
+
+///Performs requests with libcurl.
+///
+/// Each `CurlTransport` keeps its own pool of handles, and so of connections.
+pub struct CurlTransport {
+    session: Arc<Session>,
+}
+impl CurlTransport {
+    pub fn new() -> Self {
+        CurlTransport::with_session(&SessionConfig::default()).expect("default session")
+    }
+    pub(crate) fn with_session(config: &SessionConfig) -> Result<Self,Error> {
+        Ok(CurlTransport { session: Arc::new(Session { config: config.clone(), idle: Mutex::new(Vec::new()) }) })
+    }
+}
+impl Default for CurlTransport {
+    fn default() -> Self {
+        CurlTransport::new()
+    }
+}
+impl Transport for CurlTransport {
+    fn perform(&self, request: RequestDescription) -> BoxFuture<Result<Response,Error>> {
+        Box::pin(perform(self.session.clone(), request))
+    }
+    fn download(&self, request: RequestDescription) -> BoxFuture<Result<Downloaded,Error>> {
+        Box::pin(download(self.session.clone(), request))
+    }
+}
+
+#[cfg(test)] mod test {
+    use crate::{Request, Method};
+    use crate::test_server::{TestServer, large_body};
+    use pcore::pstr;
+    use pcore::release_pool::autoreleasepool;
+    #[test] fn github() {
+        let server = TestServer::start();
+        autoreleasepool(|pool| {
+            let r = Request::new(server.url("/echo/headers"), pool).unwrap();
+            let future = r
+                .header(pstr!("Accept"),Some(pstr!("application/vnd.github.v3+json")), pool)
+                .header(pstr!("Authorization"), Some(pstr!("token foobar")), pool)
+                .perform(pool);
+            let result = kiruna::test::test_await(future, std::time::Duration::from_secs(10));
+            let response = result.unwrap();
+            let data = response.check_status().unwrap();
+            let headers = String::from_utf8_lossy(data.as_slice()).to_ascii_lowercase();
+            assert!(headers.contains("accept: application/vnd.github.v3+json\r\n"));
+            assert!(headers.contains("authorization: token foobar\r\n"));
+        });
+    }
+
+    #[test] fn post_body() {
+        let server = TestServer::start();
+        autoreleasepool(|pool| {
+            let future = Request::new(server.url("/echo"), pool).unwrap()
+                .method(Method::Put)
+                .body(Box::new(*b"payload"))
+                .perform(pool);
+            let response = kiruna::test::test_await(future, std::time::Duration::from_secs(10)).unwrap();
+            assert_eq!(response.check_status().unwrap().as_slice(), b"payload");
+            let future = Request::new(server.url("/status/404"), pool).unwrap().perform(pool);
+            let response = kiruna::test::test_await(future, std::time::Duration::from_secs(10)).unwrap();
+            assert_eq!(response.check_status().unwrap_err().0, 404);
+        });
+    }
+
+    #[test] fn write_error() {
+        use super::{Transfer, Sink, HeaderLines, IdleTimer};
+        use curl::easy::Handler;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("read-only");
+        std::fs::write(&path, b"").unwrap();
+        let file = std::fs::File::open(&path).unwrap();
+        let mut transfer = Transfer { sink: Sink::File(file), headers: HeaderLines::default(), idle: IdleTimer::new(None), cancellation: None, upload: None, write_error: None };
+        assert_eq!(transfer.write(b"data").unwrap(), 0);
+        //CURLE_WRITE_ERROR is a local problem, not one to retry
+        let error = transfer.error(curl::Error::new(23));
+        assert!(matches!(error, crate::Error::Io(_)), "{:?}", error);
+        assert!(!error.is_retryable());
+    }
+
+    #[test] fn download() {
+        let server = TestServer::start();
+        autoreleasepool(|pool| {
+            let r = Request::new(server.url("/large/100000"), pool).unwrap();
+            let future = r
+                .download(pool);
+
+            let result = kiruna::test::test_await(future, std::time::Duration::from_secs(10));
+            let response = result.unwrap();
+            response.check_status().unwrap();
+            let path = response.copy_path();
+            assert_eq!(path.file_name().unwrap(), "100000");
+            assert_eq!(std::fs::read(&path).unwrap(), large_body(100000));
+            drop(response);
+            assert!(!path.exists());
+        });
+    }
+
+    #[test] fn download_status() {
+        let server = TestServer::start();
+        autoreleasepool(|pool| {
+            let future = Request::new(server.url("/status/500"), pool).unwrap().download(pool);
+            let response = kiruna::test::test_await(future, std::time::Duration::from_secs(10)).unwrap();
+            assert!(matches!(response.check_status(), Err(crate::Error::StatusCode(s)) if s == 500));
+        });
+    }
+
+    #[test] fn client_options() {
+        let server = TestServer::start();
+        autoreleasepool(|pool| {
+            let client = crate::Client::builder().follow_redirects(false).user_agent(Some("tests")).build().unwrap();
+            let future = client.request(server.url("/redirect/1"), pool).unwrap().perform(pool);
+            let response = kiruna::test::test_await(future, std::time::Duration::from_secs(10)).unwrap();
+            assert_eq!(response.status(), 302);
+            //the handle is reused without the previous request's options
+            let future = Request::new(server.url("/redirect/1"), pool).unwrap()
+                .transport(client.transport().clone())
+                .perform(pool);
+            let response = kiruna::test::test_await(future, std::time::Duration::from_secs(10)).unwrap();
+            assert_eq!(response.status(), 302);
+            let future = client.request(server.url("/echo/headers"), pool).unwrap().perform(pool);
+            let response = kiruna::test::test_await(future, std::time::Duration::from_secs(10)).unwrap();
+            let headers = String::from_utf8_lossy(response.data().as_slice()).to_ascii_lowercase();
+            assert!(headers.contains("user-agent: tests\r\n"), "{}", headers);
+        });
+    }
+}
@@ -1,2 +1,3 @@
-pub mod request;
-pub mod response;
\ No newline at end of file
+mod request;
+pub(crate) mod response;
+pub use request::URLSessionTransport;
\ No newline at end of file
@@ -1,23 +1,19 @@
-use foundationr::{NSMutableURLRequest, NSURL, NSURLSession, autoreleasepool, NSURLSessionDataTask, NSURLSessionDownloadTask, NSString, DataTaskResult, NSError, NSURLResponse, NSData};
-use objr::bindings::{StrongMutCell, ActiveAutoreleasePool, StrongLifetimeCell, StrongCell};
-use crate::Error;
-use super::response::{Response, Downloaded};
+use foundationr::{NSMutableURLRequest, NSURL, NSURLSession, NSURLSessionConfiguration, autoreleasepool, NSURLSessionDataTask, NSURLSessionDownloadTask, NSString, DataTaskResult, NSError, NSURLResponse, NSData, NSStream, NSOutputStream};
+use objr::bindings::{StrongMutCell, ActiveAutoreleasePool, StrongCell};
+use crate::{Error, PlatformError, Response, Downloaded, StatusCode, Body};
+use crate::request::RequestDescription;
+use crate::transport::{Transport, BoxFuture, AssertSend};
+use crate::header::HeaderMap;
+use crate::response::Metadata;
+use crate::client::SessionConfig;
+use super::response::Buffer;
 use blocksr::continuation::Continuation;
+use std::io::Read;
 use std::path::{PathBuf};
+use std::sync::{Arc, Mutex};
 use tempfile::tempdir;
-use pcore::string::{IntoParameterString, ParameterString};
-use pcore::release_pool::ReleasePool;
+use pcore::release_pool::{ReleasePool, autoreleasepool as pool_scope};
 use std::future::Future;
-use std::collections::HashMap;
-use pcore::pstr;
-
-pub struct Request<'a> {
-    url: StrongLifetimeCell<'a, NSString>,
-    headers: HashMap<ParameterString<'a>, ParameterString<'a>>,
-    body: Option<Box<[u8]>>,
-    method: ParameterString<'a>,
-    file_name: String
-}
 
 struct DataTaskDropper(StrongMutCell<NSURLSessionDataTask>);
 impl Drop for DataTaskDropper {
@@ -36,196 +32,361 @@ impl Drop for DownloadTaskDropper {
     }
 }
 
-impl<'a> Request<'a> {
-    ///Create a new builder with the given URL.
-    ///
-    /// On some platforms, Error::InvalidURL may be raised int his method
-    pub fn new<U: IntoParameterString<'a>>(url: U, pool: &ReleasePool) ->
-    Result<Request<'a>,Error> {
-        let url = url.into_nsstring(pool);
-        let url_buffer = url.to_str(pool);
-        let proposed_file_name = url_buffer.rsplit("/").next().ok_or_else(|| Error::InvalidURL(url.to_string()))?;
-        let file_name =  if proposed_file_name.is_empty() {
-            "requestsr"
-        }
-        else {
-            proposed_file_name
-        }.to_owned();
-        Ok(Request {
-            url: url,
-            file_name,
-            headers: HashMap::new(),
-            body: None,
-            method: pstr!("GET").into_parameter_string(pool),
-        })
+///Sorts an error from `NSURLErrorDomain` by its code.
+fn nserror(error: StrongCell<NSError>) -> Error {
+    let (code, description) = autoreleasepool(|pool| {
+        let code = error.code(pool);
+        (code, format!("{} ({} {})", error.localizedDescription(pool).to_str(pool), error.domain(pool).to_str(pool), code))
+    });
+    let e = PlatformError::from_pcore(pcore::error::Error::from_nserror(error), description);
+    match code {
+        //NSURLErrorCancelled
+        -999 => Error::Cancelled,
+        //NSURLErrorTimedOut
+        -1001 => Error::Timeout(e),
+        //NSURLErrorCannotFindHost, NSURLErrorDNSLookupFailed
+        -1003 | -1006 => Error::Dns(e),
+        //NSURLErrorCannotConnectToHost, NSURLErrorNetworkConnectionLost, NSURLErrorNotConnectedToInternet,
+        //NSURLErrorInternationalRoamingOff, NSURLErrorDataNotAllowed
+        -1004 | -1005 | -1009 | -1018 | -1020 => Error::Connect(e),
+        //NSURLErrorHTTPTooManyRedirects
+        -1007 => Error::TooManyRedirects(e),
+        //NSURLErrorBadServerResponse, NSURLErrorZeroByteResource, NSURLErrorCannotDecodeRawData,
+        //NSURLErrorCannotDecodeContentData, NSURLErrorCannotParseResponse
+        -1011 | -1014 | -1015 | -1016 | -1017 => Error::BodyRead(e),
+        //NSURLErrorSecureConnectionFailed through NSURLErrorClientCertificateRequired, and NSURLErrorAppTransportSecurityRequiresSecureConnection
+        -1206..=-1200 | -1022 => Error::Tls(e),
+        _ => Error::Platform(e),
     }
-    ///Set (or unset) a header field
-    pub fn header<K: IntoParameterString<'a>,V:IntoParameterString<'a>>(mut self, key: K,value: Option<V>, pool: &ReleasePool) -> Self {
-        match value {
-            Some(v) => {self.headers.insert(key.into_parameter_string(pool), v.into_parameter_string(pool));}
-            None => {
-                self.headers.remove(&key.into_parameter_string(pool));
-            }
+}
+
+///Reads the status, final URL and `allHeaderFields` from the response.
+///
+/// `NSHTTPURLResponse` reports neither the reason phrase nor the HTTP version.
+fn metadata(response: &NSURLResponse, pool: &ActiveAutoreleasePool) -> Metadata {
+    let mut headers = HeaderMap::new();
+    if let Some(fields) = response.allHeaderFields(pool) {
+        for (name, value) in fields.iter(pool) {
+            headers.append(&name.to_str(pool), &value.to_str(pool));
         }
-        self
     }
-    ///Set the HTTP method.
-    ///Set the HTTP method.
-    pub fn method<P: IntoParameterString<'a>>(mut self, method: P, pool: &ReleasePool) -> Self{
-        self.method = method.into_parameter_string(pool);
-        self
-
+    Metadata {
+        code: StatusCode::from_platform(response.statusCode(pool) as u16),
+        reason: None,
+        version: None,
+        url: response.URL(pool).map(|url| url.absoluteString(pool).to_str(pool).to_owned()).unwrap_or_default(),
+        headers,
+        ..Metadata::default()
     }
-    ///Set the HTTP body data.
-    pub fn body(&mut self, body: Box<[u8]>) -> &mut Self {
-        self.body = Some(body);
-        self
+}
+
+///Where the thread feeding a streamed body leaves the error if a file can't be read.
+///
+/// NSURLSession only sees the body end early, so this error is reported in place of the one it gives.
+#[derive(Clone,Default)]
+struct UploadError(Arc<Mutex<Option<std::io::Error>>>);
+impl UploadError {
+    ///The upload's error if there was one, otherwise `error` sorted by [nserror].
+    fn or(&self, error: StrongCell<NSError>) -> Error {
+        match self.0.lock().unwrap().take() {
+            Some(e) => Error::Io(e),
+            None => nserror(error),
+        }
     }
+}
 
-    pub fn perform(self, pool: &ReleasePool) -> impl Future<Output=Result<Response,Error>>  {
-        //Need to manually implement this to avoid holding the autoreleasepool over a suspend point
+///The writing end of a bound stream pair, which only the feeding thread uses.
+struct Feed(StrongMutCell<NSOutputStream>);
+unsafe impl Send for Feed {}
 
-        //The below is a bit tricky, but basically it boils down to getting the "nil case" inside the future, since
-        //we can only return 1 future
-        enum FutureInput {
-            Continuation(Continuation<DataTaskDropper,DataTaskResult>),
-            Error(Error)
-        }
-        let input = match NSURL::from_string(&self.url, pool) {
-            None => {
-                FutureInput::Error(Error::InvalidURL(self.url.to_str(pool).to_owned()))
-            }
-            Some(u) => {
-                let mut request = NSMutableURLRequest::from_url(&u, pool);
-                request.setHTTPMethod(&self.method.into_nsstring(pool), pool);
-                match self.body {
-                    None => {}
-                    Some(bytes) => {request.setHTTPBody(&NSData::from_boxed_bytes(bytes,pool), pool)}
-                }
-                for header in self.headers {
-                    request.setValueForHTTPHeaderField(Some(&header.1.into_nsstring(pool)), &header.0.into_nsstring(pool), pool);
-                }
-                let session = NSURLSession::shared(&pool);
-                let (mut continuation, completion) = Continuation::new();
-                let mut task = session.dataTaskWithRequestCompletionHandler(request.as_immutable(),&pool, |result| {
-                    completion.complete(result);
-                });
-                task.resume(&pool);
-                continuation.accept(DataTaskDropper(task));
-                FutureInput::Continuation(continuation)
-            }
-        };
-
-        async {
-            match input {
-                FutureInput::Continuation(continuation) => {
-                    let result = continuation.await
-                        //erase the partial response
-                        .map_err(|e| {
-                            Error::PcoreError(pcore::error::Error::from_nserror(e.0))
-                        })?;
-                    Ok(Response::new(result.1, result.0))
+///Sets `body` as the `HTTPBodyStream` of `url_request`, written from a thread as NSURLSession reads it.
+///
+/// This is the stream pair Apple suggests for bodies made up of several sources.  Writes block while the pair's
+/// buffer is full, so only that much of the body is in memory at once.  NSURLSession can't rewind the stream, so
+/// without a delegate to supply a new one, a 307 or 308 redirect of a streamed body fails.
+fn stream_body(url_request: &mut NSMutableURLRequest, body: Body, error: UploadError, pool: &ReleasePool) {
+    const BUFFER_SIZE: usize = 64 * 1024;
+    let (input, output) = NSStream::getBoundStreamsWithBufferSize(BUFFER_SIZE, pool);
+    url_request.setHTTPBodyStream(&input, pool);
+    //without a length, NSURLSession would send the stream chunked
+    url_request.setValueForHTTPHeaderField(Some(&NSString::with_str_copy(&body.len().to_string(), pool)), &NSString::with_str_copy("Content-Length", pool), pool);
+    let feed = Feed(output);
+    std::thread::Builder::new().name("requestr-upload".to_owned()).spawn(move || autoreleasepool(|pool| {
+        let mut output = feed.0;
+        output.open(pool);
+        let mut reader = body.reader();
+        let mut buffer = vec![0; BUFFER_SIZE];
+        'feed: loop {
+            let read = match reader.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(e) => {
+                    *error.0.lock().unwrap() = Some(e);
+                    break
                 }
-                FutureInput::Error(e) => {
-                    Err(e)
+            };
+            let mut written = 0;
+            while written < read {
+                //NSURLSession closes its end when the task finishes or is cancelled
+                match output.write(&buffer[written..read], pool) {
+                    n if n > 0 => written += n as usize,
+                    _ => break 'feed,
                 }
             }
         }
+        output.close(pool);
+    })).expect("Can't spawn thread");
+}
 
+///Converts the builder into an `NSMutableURLRequest`.
+///
+/// A body in memory is set as the `HTTPBody`; one read from files is streamed, and reports read errors through the
+/// returned [UploadError].
+///
+/// # Errors
+/// [Error::InvalidURL] if Foundation won't accept the URL.
+fn url_request(request: RequestDescription, pool: &ReleasePool) -> Result<(StrongMutCell<NSMutableURLRequest>, UploadError),Error> {
+    let url = NSString::with_str_copy(request.url.as_str(), pool);
+    let url = NSURL::from_string(&url, pool).ok_or_else(|| Error::InvalidURL(request.url.to_string()))?;
+    let mut url_request = NSMutableURLRequest::from_url(&url, pool);
+    url_request.setHTTPMethod(&NSString::with_str_copy(request.method.as_str(), pool), pool);
+    //NSURLSession's timeout is the longest it waits for any data, including while connecting
+    let timeouts = request.timeouts;
+    if let Some(interval) = [timeouts.connect, timeouts.read_idle].into_iter().flatten().min() {
+        url_request.setTimeoutInterval(interval.as_secs_f64(), pool);
+    }
+    for (name, value) in request.headers.combined() {
+        url_request.setValueForHTTPHeaderField(Some(&NSString::with_str_copy(&value, pool)), &NSString::with_str_copy(name, pool), pool);
     }
+    //NSURLSession adds its own User-Agent unless the request has one; an empty one is left out altogether
+    if !request.headers.contains("User-Agent") {
+        url_request.setValueForHTTPHeaderField(Some(&NSString::with_str_copy("", pool)), &NSString::with_str_copy("User-Agent", pool), pool);
+    }
+    let upload_error = UploadError::default();
+    match request.body {
+        None => {}
+        Some(body) => match body.into_bytes() {
+            Ok(bytes) => url_request.setHTTPBody(&NSData::from_boxed_bytes(bytes, pool), pool),
+            Err(body) => stream_body(&mut url_request, body, upload_error.clone(), pool),
+        }
+    }
+    Ok((url_request, upload_error))
+}
 
-    ///Downloads the request into a file.
-    ///
-    /// The file will be located in a temporary directory and will be deleted when the return value is dropped.
-    pub fn download(self, pool: &ReleasePool) -> impl Future<Output=Result<Downloaded,Error>>{
-        //Need to manually implement this to a) avoid holding the autoreleasepool over a suspend point, b) move inside closure
-        //The below is a bit tricky, but basically it boils down to getting the "nil case" inside the future, since
-        //we can only return 1 future
-        enum FutureInput {
-            Continuation(Continuation<DownloadTaskDropper, Result<Downloaded, (StrongCell<NSError>, Option<StrongCell<NSURLResponse>>)>>),
-            Error(Error)
+fn perform(session: &NSURLSession, request: RequestDescription, pool: &ReleasePool) -> impl Future<Output=Result<Response,Error>>  {
+    //Need to manually implement this to avoid holding the autoreleasepool over a suspend point
+
+    //The below is a bit tricky, but basically it boils down to getting the "nil case" inside the future, since
+    //we can only return 1 future
+    enum FutureInput {
+        Continuation(Continuation<DataTaskDropper,DataTaskResult>, UploadError),
+        Error(Error)
+    }
+    let input = match url_request(request, pool) {
+        Err(e) => {
+            FutureInput::Error(e)
+        }
+        Ok((request, upload_error)) => {
+            let (mut continuation, completion) = Continuation::new();
+            let mut task = session.dataTaskWithRequestCompletionHandler(request.as_immutable(),&pool, |result| {
+                completion.complete(result);
+            });
+            task.resume(&pool);
+            continuation.accept(DataTaskDropper(task));
+            FutureInput::Continuation(continuation, upload_error)
         }
-        let input = match NSURL::from_string(&self.url,pool) {
-            None => {
-                FutureInput::Error(Error::InvalidURL(self.url.to_str(pool).to_string()))
+    };
+
+    async {
+        match input {
+            FutureInput::Continuation(continuation, upload_error) => {
+                let result = continuation.await
+                    //erase the partial response
+                    .map_err(|e| upload_error.or(e.0))?;
+                let metadata = autoreleasepool(|pool| metadata(&result.1, pool));
+                Ok(Response::from_metadata(metadata, Buffer(result.0).into()))
             }
-            Some(url) => {
-                let mut request = NSMutableURLRequest::from_url(&url,pool);
-                request.setHTTPMethod(&self.method.into_nsstring(pool), pool);
-                match self.body {
-                    None => {}
-                    Some(bytes) => {request.setHTTPBody(&NSData::from_boxed_bytes(bytes,pool), pool)}
-                }
-                for header in self.headers {
-                    request.setValueForHTTPHeaderField(Some(&header.1.into_nsstring(pool)), &header.0.into_nsstring(pool), pool);
-                }
-                let session = NSURLSession::shared(&pool);
-                let (mut continuation, completion) = Continuation::new();
-                //need to be able to send the filename into the completion handler
-                let move_filename = self.file_name.clone();
-                let mut task = session.downloadTaskWithRequestCompletionHandler(request.as_immutable(),&pool, move |result| {
-                    let result = result.map(|r| {
-                        //I assume there's a pool when we're called back from foundation
-                        let pool = unsafe{ ActiveAutoreleasePool::assume_autoreleasepool() };
-                        let current_path = PathBuf::from(r.0.path(&pool).unwrap().to_str(&pool));
-                        eprintln!("original path {:?}",current_path);
-                        let dir = tempdir().unwrap();
-
-                        let new_path = dir.path().join(move_filename);
-                        std::fs::rename(current_path,new_path.clone()).unwrap();
-                        Downloaded::new(dir,new_path, r.1.statusCode(&pool) as u16)
-                    });
-                    completion.complete(result);
-                });
-                task.resume(&pool);
-                continuation.accept(DownloadTaskDropper(task));
-                FutureInput::Continuation(continuation)
+            FutureInput::Error(e) => {
+                Err(e)
             }
+        }
+    }
 
-        };
-        async {
-            match input {
-                FutureInput::Continuation(c) => {
-                    let result = c.await.map_err(|e| {
-                        Error::PcoreError(pcore::error::Error::from_nserror(e.0))
-                    });
-                    result
-                }
-                FutureInput::Error(e) => {Err(e)}
+}
+
+///Downloads the request into a file.
+///
+/// The file will be located in a temporary directory and will be deleted when the return value is dropped.
+fn download(session: &NSURLSession, request: RequestDescription, pool: &ReleasePool) -> impl Future<Output=Result<Downloaded,Error>>{
+    //Need to manually implement this to a) avoid holding the autoreleasepool over a suspend point, b) move inside closure
+    //The below is a bit tricky, but basically it boils down to getting the "nil case" inside the future, since
+    //we can only return 1 future
+    enum FutureInput {
+        Continuation(Continuation<DownloadTaskDropper, Result<Result<Downloaded,Error>, (StrongCell<NSError>, Option<StrongCell<NSURLResponse>>)>>, UploadError),
+        Error(Error)
+    }
+    //need to be able to send the filename into the completion handler
+    let move_filename = request.file_name.clone();
+    let input = match url_request(request, pool) {
+        Err(e) => {
+            FutureInput::Error(e)
+        }
+        Ok((request, upload_error)) => {
+            let (mut continuation, completion) = Continuation::new();
+            let mut task = session.downloadTaskWithRequestCompletionHandler(request.as_immutable(),&pool, move |result| {
+                let result = result.map(|r| {
+                    //I assume there's a pool when we're called back from foundation
+                    let pool = unsafe{ ActiveAutoreleasePool::assume_autoreleasepool() };
+                    let current_path = PathBuf::from(r.0.path(&pool).unwrap().to_str(&pool));
+                    let dir = tempdir().map_err(Error::Io)?;
+                    let new_path = dir.path().join(move_filename);
+                    //foundation deletes the file once we return, so it must be moved now
+                    std::fs::rename(current_path,new_path.clone()).map_err(Error::Io)?;
+                    Ok(Downloaded::new(dir,new_path, metadata(&r.1, &pool)))
+                });
+                completion.complete(result);
+            });
+            task.resume(&pool);
+            continuation.accept(DownloadTaskDropper(task));
+            FutureInput::Continuation(continuation, upload_error)
+        }
+
+    };
+    async {
+        match input {
+            FutureInput::Continuation(c, upload_error) => {
+                c.await.map_err(|e| upload_error.or(e.0))?
             }
+            FutureInput::Error(e) => {Err(e)}
         }
     }
+}
 
+///Performs requests with `NSURLSession`.
+///
+/// [URLSessionTransport::new] uses the shared session.  A [crate::Client] gets a session of its own, and so its own
+/// connections.
+pub struct URLSessionTransport {
+    session: StrongCell<NSURLSession>,
+}
+//NSURLSession is thread-safe
+unsafe impl Send for URLSessionTransport {}
+unsafe impl Sync for URLSessionTransport {}
+impl URLSessionTransport {
+    pub fn new() -> Self {
+        autoreleasepool(|pool| URLSessionTransport { session: NSURLSession::shared(pool) })
+    }
+    ///`NSURLSession` can only change redirects, proxies and certificate checks through a delegate, so the session
+    /// uses the default configuration and the system's settings.
+    ///
+    /// # Errors
+    /// [Error::Unsupported] if `config` asks not to follow redirects, for a proxy, or to accept invalid certificates,
+    /// rather than quietly ignoring it.
+    pub(crate) fn with_session(config: &SessionConfig) -> Result<Self,Error> {
+        let unsupported = [
+            (!config.follow_redirects, "follow_redirects(false)"),
+            (config.proxy.is_some(), "proxy"),
+            (config.accept_invalid_certs, "danger_accept_invalid_certs"),
+        ];
+        if let Some((_, option)) = unsupported.iter().find(|u| u.0) {
+            return Err(Error::Unsupported(format!("{} on NSURLSession", option)));
+        }
+        autoreleasepool(|pool| {
+            let configuration = NSURLSessionConfiguration::defaultSessionConfiguration(pool);
+            Ok(URLSessionTransport { session: NSURLSession::sessionWithConfiguration(&configuration, pool) })
+        })
+    }
+}
+impl Default for URLSessionTransport {
+    fn default() -> Self {
+        URLSessionTransport::new()
+    }
 }
+impl Transport for URLSessionTransport {
+    //The futures hold session tasks, which NSURLSession documents as thread-safe, and the immutable NSData,
+    //NSURLResponse and NSError it completes them with.
+    fn perform(&self, request: RequestDescription) -> BoxFuture<Result<Response,Error>> {
+        pool_scope(|pool| Box::pin(unsafe { AssertSend::new(perform(&self.session, request, pool)) }))
+    }
+    fn download(&self, request: RequestDescription) -> BoxFuture<Result<Downloaded,Error>> {
+        pool_scope(|pool| Box::pin(unsafe { AssertSend::new(download(&self.session, request, pool)) }))
+    }
+}
+
 #[cfg(test)] mod test {
-    use crate::Request;
+    use crate::{Request, Method};
+    use crate::test_server::{TestServer, large_body};
     use pcore::pstr;
     use pcore::release_pool::autoreleasepool;
     #[test] fn github() {
+        let server = TestServer::start();
         autoreleasepool(|pool| {
-            let r = Request::new(pstr!("https://sealedabstract.com"), pool).unwrap();
+            let r = Request::new(server.url("/echo/headers"), pool).unwrap();
             let future = r
                 .header(pstr!("Accept"),Some(pstr!("application/vnd.github.v3+json")), pool)
                 .header(pstr!("Authorization"), Some(pstr!("token foobar")), pool)
                 .perform(pool);
             let result = kiruna::test::test_await(future, std::time::Duration::from_secs(10));
             let response = result.unwrap();
-            let data = response.check_status(pool).unwrap();
-            println!("{:?}",data);
+            let data = response.check_status().unwrap();
+            let headers = String::from_utf8_lossy(data.as_slice()).to_ascii_lowercase();
+            assert!(headers.contains("accept: application/vnd.github.v3+json\r\n"));
+            assert!(headers.contains("authorization: token foobar\r\n"));
         });
+    }
 
+    #[test] fn post_body() {
+        let server = TestServer::start();
+        autoreleasepool(|pool| {
+            let future = Request::new(server.url("/echo"), pool).unwrap()
+                .method(Method::Put)
+                .body(Box::new(*b"payload"))
+                .perform(pool);
+            let response = kiruna::test::test_await(future, std::time::Duration::from_secs(10)).unwrap();
+            assert_eq!(response.check_status().unwrap().as_slice(), b"payload");
+            let future = Request::new(server.url("/status/404"), pool).unwrap().perform(pool);
+            let response = kiruna::test::test_await(future, std::time::Duration::from_secs(10)).unwrap();
+            assert_eq!(response.check_status().unwrap_err().0, 404);
+        });
     }
 
     #[test] fn download() {
+        let server = TestServer::start();
         autoreleasepool(|pool| {
-            let r = Request::new(pstr!("https://sealedabstract.com/index.html"), pool).unwrap();
+            let r = Request::new(server.url("/large/100000"), pool).unwrap();
             let future = r
                 .download(pool);
 
             let result = kiruna::test::test_await(future, std::time::Duration::from_secs(10));
             let response = result.unwrap();
-            println!("{:?}", response);
+            response.check_status().unwrap();
+            let path = response.copy_path();
+            assert_eq!(path.file_name().unwrap(), "100000");
+            assert_eq!(std::fs::read(&path).unwrap(), large_body(100000));
+            drop(response);
+            assert!(!path.exists());
         });
     }
-}
 
+    #[test] fn unsupported_session_options() {
+        use crate::{Client, Error};
+        let builders = [
+            Client::builder().follow_redirects(false),
+            Client::builder().proxy("http://127.0.0.1:8080"),
+            Client::builder().danger_accept_invalid_certs(true),
+        ];
+        for builder in builders {
+            assert!(matches!(builder.build(), Err(Error::Unsupported(_))));
+        }
+        assert!(Client::builder().follow_redirects(true).build().is_ok());
+    }
+
+    #[test] fn download_status() {
+        let server = TestServer::start();
+        autoreleasepool(|pool| {
+            let future = Request::new(server.url("/status/500"), pool).unwrap().download(pool);
+            let response = kiruna::test::test_await(future, std::time::Duration::from_secs(10)).unwrap();
+            assert!(matches!(response.check_status(), Err(crate::Error::StatusCode(s)) if s == 500));
+        });
+    }
+}
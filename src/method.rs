@@ -0,0 +1,126 @@
+/*!
+Request methods.
+*/
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use crate::Error;
+
+///An HTTP request method.
+///
+/// Methods are case-sensitive, so `Method::new("get")` is an extension method, not [Method::Get].
+#[derive(Debug,Clone,PartialEq,Eq,Hash,Default)]
+pub enum Method {
+    #[default]
+    Get,
+    Head,
+    Post,
+    Put,
+    Patch,
+    Delete,
+    Options,
+    Connect,
+    Trace,
+    ///Any other method, such as WebDAV's `PROPFIND`.  Made by [Method::new].
+    Extension(ExtensionMethod),
+}
+
+///The name of a method outside the standard set.  See [Method::Extension].
+#[derive(Debug,Clone,PartialEq,Eq,Hash)]
+pub struct ExtensionMethod(String);
+impl ExtensionMethod {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Method {
+    ///Parses `method`, which is one of the standard methods or else must be an RFC 9110 `token`.
+    ///
+    /// # Errors
+    /// [Error::InvalidMethod] if `method` is empty or contains characters a token doesn't allow, such as spaces.
+    pub fn new(method: &str) -> Result<Method,Error> {
+        Ok(match method {
+            "GET" => Method::Get,
+            "HEAD" => Method::Head,
+            "POST" => Method::Post,
+            "PUT" => Method::Put,
+            "PATCH" => Method::Patch,
+            "DELETE" => Method::Delete,
+            "OPTIONS" => Method::Options,
+            "CONNECT" => Method::Connect,
+            "TRACE" => Method::Trace,
+            other if crate::header::is_token(other) => Method::Extension(ExtensionMethod(other.to_owned())),
+            other => return Err(Error::InvalidMethod(other.to_owned())),
+        })
+    }
+    ///The method as it's sent, such as `GET`.
+    pub fn as_str(&self) -> &str {
+        match self {
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Patch => "PATCH",
+            Method::Delete => "DELETE",
+            Method::Options => "OPTIONS",
+            Method::Connect => "CONNECT",
+            Method::Trace => "TRACE",
+            Method::Extension(method) => method.as_str(),
+        }
+    }
+    ///Whether RFC 9110 defines the method as safe (read-only): `GET`, `HEAD`, `OPTIONS` and `TRACE`.
+    pub fn is_safe(&self) -> bool {
+        matches!(self, Method::Get | Method::Head | Method::Options | Method::Trace)
+    }
+    ///Whether RFC 9110 defines the method as idempotent: the safe methods, `PUT` and `DELETE`.
+    ///
+    /// Extension methods are assumed not to be.
+    pub fn is_idempotent(&self) -> bool {
+        self.is_safe() || matches!(self, Method::Put | Method::Delete)
+    }
+}
+impl Display for Method {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+impl FromStr for Method {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Method::new(s)
+    }
+}
+impl PartialEq<str> for Method {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+impl PartialEq<&str> for Method {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+#[cfg(test)] mod test {
+    use super::Method;
+    use crate::Error;
+
+    #[test] fn parse() {
+        assert_eq!(Method::new("GET").unwrap(), Method::Get);
+        assert_eq!("DELETE".parse::<Method>().unwrap(), Method::Delete);
+        let propfind = Method::new("PROPFIND").unwrap();
+        assert!(matches!(&propfind, Method::Extension(m) if m.as_str() == "PROPFIND"));
+        assert_eq!(propfind.to_string(), "PROPFIND");
+        assert_eq!(Method::new("get").unwrap(), "get");
+        for invalid in ["", "GET /evil", "GET\r\n", "M\u{e9}THODE"] {
+            assert!(matches!(Method::new(invalid), Err(Error::InvalidMethod(m)) if m == invalid), "{:?}", invalid);
+        }
+    }
+
+    #[test] fn properties() {
+        assert!(Method::Get.is_safe() && Method::Get.is_idempotent());
+        assert!(!Method::Put.is_safe() && Method::Put.is_idempotent());
+        assert!(!Method::Post.is_idempotent() && !Method::Patch.is_idempotent());
+        assert!(!Method::new("PROPFIND").unwrap().is_idempotent());
+    }
+}
@@ -0,0 +1,213 @@
+/*!
+An in-memory [Transport] for unit tests.
+
+[MockTransport] answers requests from canned responses instead of the network, and records every request
+it receives so tests can make assertions about them afterwards.
+
+```
+use std::sync::Arc;
+use std::time::Duration;
+use requestr::{Request, Method, StatusCode};
+use requestr::mock::{MockTransport, Matcher};
+use pcore::pstr;
+# pcore::release_pool::autoreleasepool(|pool| {
+let mock = Arc::new(MockTransport::new());
+mock.on(Matcher::any().method(Method::Post).url("https://example.com/api"), StatusCode::CREATED, b"created");
+
+let future = Request::new(pstr!("https://example.com/api"), pool).unwrap()
+    .method(Method::Post)
+    .body(Box::new(*b"hello"))
+    .transport(mock.clone())
+    .perform(pool);
+let response = kiruna::test::test_await(future, Duration::from_secs(1)).unwrap();
+assert_eq!(response.check_status().unwrap().as_slice(), b"created");
+
+mock.assert_calls(&Matcher::any().method(Method::Post), 1);
+mock.assert_body(&Matcher::any(), b"hello");
+# });
+```
+*/
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, Mutex};
+use crate::{Error, Response, Method, StatusCode};
+use crate::request::RequestDescription;
+use crate::transport::{Transport, BoxFuture};
+
+type Predicate = Arc<dyn Fn(&RequestDescription) -> bool + Send + Sync>;
+type Reply = Arc<dyn Fn(&RequestDescription) -> Result<Response,Error> + Send + Sync>;
+
+///Selects requests by method, URL, headers or an arbitrary predicate.
+///
+/// Every condition that has been set must hold for the matcher to match.
+#[derive(Clone,Default)]
+pub struct Matcher {
+    method: Option<Method>,
+    url: Option<String>,
+    headers: Vec<(String, String)>,
+    predicates: Vec<Predicate>,
+}
+impl Matcher {
+    ///Matches every request.
+    pub fn any() -> Self {
+        Matcher::default()
+    }
+    ///Also require this method.
+    pub fn method(self, method: Method) -> Self {
+        Matcher { method: Some(method), ..self }
+    }
+    ///Also require exactly this URL.
+    pub fn url(self, url: &str) -> Self {
+        Matcher { url: Some(url.to_owned()), ..self }
+    }
+    ///Also require a header with this name (compared case-insensitively) and value.
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_owned(), value.to_owned()));
+        self
+    }
+    ///Also require `predicate` to return `true`.
+    pub fn when<F: Fn(&RequestDescription) -> bool + Send + Sync + 'static>(mut self, predicate: F) -> Self {
+        self.predicates.push(Arc::new(predicate));
+        self
+    }
+    ///Whether `request` meets every condition.
+    pub fn matches(&self, request: &RequestDescription) -> bool {
+        self.method.as_ref().is_none_or(|m| m == request.method()) &&
+            self.url.as_ref().is_none_or(|u| u == request.url().as_str()) &&
+            self.headers.iter().all(|(name, value)| {
+                request.headers().get_all(name).any(|v| v == value)
+            }) &&
+            self.predicates.iter().all(|p| p(request))
+    }
+}
+impl Debug for Matcher {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.method.as_ref().map_or("*", Method::as_str))?;
+        f.write_fmt(format_args!(" {}", self.url.as_deref().unwrap_or("*")))?;
+        for (name, value) in &self.headers {
+            f.write_fmt(format_args!(" [{}: {}]", name, value))?;
+        }
+        if !self.predicates.is_empty() {
+            f.write_fmt(format_args!(" (+{} predicates)", self.predicates.len()))?;
+        }
+        Ok(())
+    }
+}
+
+struct Route {
+    matcher: Matcher,
+    reply: Reply,
+}
+
+///A [Transport] that serves canned responses and records requests.
+///
+/// Routes are tried in the order they were added, and the first match answers.  A request that matches no
+/// route fails with [Error::Unmatched].
+#[derive(Default)]
+pub struct MockTransport {
+    routes: Mutex<Vec<Route>>,
+    requests: Mutex<Vec<RequestDescription>>,
+}
+impl MockTransport {
+    pub fn new() -> Self {
+        MockTransport::default()
+    }
+    ///Answer requests matching `matcher` with this status code and body.
+    pub fn on(&self, matcher: Matcher, code: StatusCode, body: &[u8]) {
+        let body = body.to_vec();
+        self.on_with(matcher, move |_| Ok(Response::new(code, body.clone().into())));
+    }
+    ///Answer requests matching `matcher` by calling `reply`.
+    ///
+    /// Use this to compute a response from the request, or to fail with an error.
+    pub fn on_with<F: Fn(&RequestDescription) -> Result<Response,Error> + Send + Sync + 'static>(&self, matcher: Matcher, reply: F) {
+        self.routes.lock().unwrap().push(Route { matcher, reply: Arc::new(reply) });
+    }
+    ///Every request received so far, in order, including ones that matched no route.
+    pub fn requests(&self) -> Vec<RequestDescription> {
+        self.requests.lock().unwrap().clone()
+    }
+    ///How many requests received so far match `matcher`.
+    pub fn calls(&self, matcher: &Matcher) -> usize {
+        self.requests.lock().unwrap().iter().filter(|r| matcher.matches(r)).count()
+    }
+    ///Panics unless exactly `expected` requests received so far match `matcher`.
+    pub fn assert_calls(&self, matcher: &Matcher, expected: usize) {
+        let actual = self.calls(matcher);
+        assert!(actual == expected, "expected {} requests matching {:?} but got {}; received {:#?}", expected, matcher, actual, self.requests());
+    }
+    ///Panics unless the most recent request matching `matcher` had this body.
+    ///
+    /// An empty `expected` also accepts a request without a body.
+    pub fn assert_body(&self, matcher: &Matcher, expected: &[u8]) {
+        let requests = self.requests.lock().unwrap();
+        let request = requests.iter().rev().find(|r| matcher.matches(r))
+            .unwrap_or_else(|| panic!("no request matching {:?}; received {:#?}", matcher, requests));
+        let actual = request.body().map(|b| b.to_vec().unwrap_or_else(|e| panic!("couldn't read body: {}", e))).unwrap_or_default();
+        assert!(actual == expected, "body of {} {} was {:?}, expected {:?}", request.method(), request.url(),
+                String::from_utf8_lossy(&actual), String::from_utf8_lossy(expected));
+    }
+    fn reply(&self, request: &RequestDescription) -> Option<Reply> {
+        self.routes.lock().unwrap().iter().find(|r| r.matcher.matches(request)).map(|r| r.reply.clone())
+    }
+}
+impl Transport for MockTransport {
+    fn perform(&self, request: RequestDescription) -> BoxFuture<Result<Response,Error>> {
+        let result = match self.reply(&request) {
+            Some(reply) => reply(&request),
+            None => Err(Error::Unmatched(format!("{} {}", request.method(), request.url()))),
+        };
+        self.requests.lock().unwrap().push(request);
+        Box::pin(std::future::ready(result))
+    }
+}
+
+#[cfg(test)] mod test {
+    use super::{MockTransport, Matcher};
+    use crate::{Request, Response, Error, Method, StatusCode};
+    use pcore::pstr;
+    use pcore::release_pool::autoreleasepool;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test] fn routes() {
+        let mock = Arc::new(MockTransport::new());
+        mock.on(Matcher::any().method(Method::Get).url("https://example.com/a").header("accept", "text/plain"), StatusCode::OK, b"plain");
+        mock.on(Matcher::any().url("https://example.com/a"), StatusCode::new(406).unwrap(), b"");
+        mock.on_with(Matcher::any().method(Method::Post), |r| Ok(Response::new(StatusCode::CREATED, r.body().unwrap().to_vec().unwrap().into())));
+        autoreleasepool(|pool| {
+            let get = |accept| {
+                let future = Request::new(pstr!("https://example.com/a"), pool).unwrap()
+                    .header(pstr!("Accept"), Some(accept), pool)
+                    .transport(mock.clone())
+                    .perform(pool);
+                kiruna::test::test_await(future, Duration::from_secs(1)).unwrap()
+            };
+            assert_eq!(get(pstr!("text/plain")).check_status().unwrap().as_slice(), b"plain");
+            assert_eq!(get(pstr!("text/html")).check_status().unwrap_err().0, 406);
+
+            let future = Request::new(pstr!("https://example.com/b"), pool).unwrap()
+                .method(Method::Post)
+                .body(Box::new(*b"echo"))
+                .transport(mock.clone())
+                .perform(pool);
+            let response = kiruna::test::test_await(future, Duration::from_secs(1)).unwrap();
+            assert_eq!(response.check_status().unwrap().as_slice(), b"echo");
+
+            let future = Request::new(pstr!("https://example.com/c"), pool).unwrap()
+                .transport(mock.clone())
+                .perform(pool);
+            let result = kiruna::test::test_await(future, Duration::from_secs(1));
+            assert!(matches!(result, Err(Error::Unmatched(ref r)) if r == "GET https://example.com/c"));
+        });
+        assert_eq!(mock.requests().len(), 4);
+        mock.assert_calls(&Matcher::any().url("https://example.com/a"), 2);
+        mock.assert_calls(&Matcher::any().method(Method::Post).when(|r| r.url().path() == "/b"), 1);
+        mock.assert_body(&Matcher::any().method(Method::Post), b"echo");
+        mock.assert_body(&Matcher::any(), b"");
+    }
+
+    #[test] #[should_panic(expected = "expected 2 requests matching DELETE *")]
+    fn assert_calls_fails() {
+        MockTransport::new().assert_calls(&Matcher::any().method(Method::Delete), 2);
+    }
+}
@@ -0,0 +1,236 @@
+/*!
+`multipart/form-data` bodies, for uploading files.
+
+```no_run
+use requestr::{Request, Method, Multipart, Part};
+# pcore::release_pool::autoreleasepool(|pool| {
+let form = Multipart::new()
+    .text("title", "Quarterly report")
+    .part("report", Part::file("report.pdf").unwrap().mime_type("application/pdf"));
+let request = Request::new("https://example.com/upload", pool).unwrap()
+    .method(Method::Post)
+    .multipart(form).unwrap();
+# });
+```
+
+File parts aren't read when they're added, only while the request is being sent, so uploading a large file doesn't
+hold it in memory.  libcurl and the std backend read the files as they send them, and `NSURLSession` reads them
+through an `HTTPBodyStream`.  WinRT's `HttpClient` can only stream a single source, so it first copies the form into
+a temporary file and streams that.
+*/
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::path::{Path, PathBuf};
+use crate::{Error, HeaderMap};
+use crate::body::{Body, Segment};
+
+///A `multipart/form-data` form.  See the [module documentation](self).
+#[derive(Debug,Clone)]
+pub struct Multipart {
+    boundary: String,
+    parts: Vec<(String, Part)>,
+}
+
+#[derive(Debug,Clone)]
+enum Content {
+    Bytes(Box<[u8]>),
+    File { path: PathBuf, len: u64 },
+}
+
+///One field of a [Multipart] form: text, bytes, or a file.
+#[derive(Debug,Clone)]
+pub struct Part {
+    content: Content,
+    file_name: Option<String>,
+    mime_type: Option<String>,
+    headers: HeaderMap,
+}
+
+///A boundary that won't turn up in the parts by chance.
+fn random_boundary() -> String {
+    let mut boundary = String::from("requestr-");
+    for _ in 0..2 {
+        //each RandomState has new random keys
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u128(std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_nanos());
+        boundary.push_str(&format!("{:016x}", hasher.finish()));
+    }
+    boundary
+}
+
+///Escapes a name or file name for a `Content-Disposition` parameter, as browsers do.
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('"', "%22").replace('\r', "%0D").replace('\n', "%0A"))
+}
+
+impl Multipart {
+    ///An empty form, with a random boundary.
+    pub fn new() -> Multipart {
+        Multipart { boundary: random_boundary(), parts: Vec::new() }
+    }
+    ///The delimiter between parts, as it appears in [Multipart::content_type].
+    pub fn boundary(&self) -> &str {
+        &self.boundary
+    }
+    ///Adds a text field.
+    pub fn text(self, name: &str, value: &str) -> Self {
+        self.part(name, Part::text(value))
+    }
+    ///Adds the file at `path`, named and typed as [Part::file] describes.
+    ///
+    /// # Errors
+    /// As for [Part::file].
+    pub fn file<P: AsRef<Path>>(self, name: &str, path: P) -> Result<Self,Error> {
+        Ok(self.part(name, Part::file(path)?))
+    }
+    ///Adds `part` as the field `name`.  Names may repeat.
+    pub fn part(mut self, name: &str, part: Part) -> Self {
+        self.parts.push((name.to_owned(), part));
+        self
+    }
+    ///The `Content-Type` of the encoded form, `multipart/form-data; boundary=...`.
+    pub fn content_type(&self) -> String {
+        format!("multipart/form-data; boundary={}", self.boundary)
+    }
+    ///Encodes the form, leaving files to be read as it's sent.
+    ///
+    /// # Errors
+    /// [Error::InvalidHeader] if a part has a header field RFC 9110 doesn't allow.
+    pub(crate) fn into_body(self) -> Result<Body,Error> {
+        let mut segments = Vec::new();
+        let mut pending = Vec::new();
+        for (name, part) in self.parts {
+            let mut disposition = format!("form-data; name={}", quote(&name));
+            if let Some(file_name) = &part.file_name {
+                disposition.push_str(&format!("; filename={}", quote(file_name)));
+            }
+            let mut headers = HeaderMap::new();
+            headers.insert("Content-Disposition", &disposition);
+            if let Some(mime_type) = &part.mime_type {
+                headers.insert("Content-Type", mime_type);
+            }
+            for (name, value) in part.headers.iter() {
+                headers.insert(name, value);
+            }
+            headers.validate()?;
+            pending.extend_from_slice(format!("--{}\r\n", self.boundary).as_bytes());
+            for (name, value) in headers.iter() {
+                pending.extend_from_slice(format!("{}: {}\r\n", name, value).as_bytes());
+            }
+            pending.extend_from_slice(b"\r\n");
+            match part.content {
+                Content::Bytes(bytes) => pending.extend_from_slice(&bytes),
+                Content::File { path, len } => {
+                    segments.push(Segment::Bytes(std::mem::take(&mut pending).into_boxed_slice()));
+                    segments.push(Segment::File { path, len });
+                }
+            }
+            pending.extend_from_slice(b"\r\n");
+        }
+        pending.extend_from_slice(format!("--{}--\r\n", self.boundary).as_bytes());
+        segments.push(Segment::Bytes(pending.into_boxed_slice()));
+        Ok(Body::from_segments(segments))
+    }
+}
+impl Default for Multipart {
+    fn default() -> Self {
+        Multipart::new()
+    }
+}
+
+impl Part {
+    ///A text field, sent without a `Content-Type`.
+    pub fn text(value: &str) -> Part {
+        Part { content: Content::Bytes(value.as_bytes().into()), file_name: None, mime_type: None, headers: HeaderMap::new() }
+    }
+    ///Bytes in memory, sent as `application/octet-stream` unless [Part::mime_type] says otherwise.
+    pub fn bytes<B: Into<Box<[u8]>>>(bytes: B) -> Part {
+        Part { content: Content::Bytes(bytes.into()), file_name: None, mime_type: Some("application/octet-stream".to_owned()), headers: HeaderMap::new() }
+    }
+    ///The file at `path`, which is read while the request is being sent.
+    ///
+    /// The file name sent is the last component of `path`, and the type is `application/octet-stream`; change them
+    /// with [Part::file_name] and [Part::mime_type].  The file's length is taken now.  If it's shorter when it's
+    /// read, the request fails with [Error::Io]; if it's longer, the rest isn't sent.
+    ///
+    /// # Errors
+    /// [Error::Io] if `path` isn't a file that can be read.
+    pub fn file<P: AsRef<Path>>(path: P) -> Result<Part,Error> {
+        let path = path.as_ref();
+        let metadata = std::fs::metadata(path).map_err(Error::Io)?;
+        if !metadata.is_file() {
+            return Err(Error::Io(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("{} isn't a file", path.display()))));
+        }
+        Ok(Part {
+            content: Content::File { path: path.to_owned(), len: metadata.len() },
+            file_name: path.file_name().map(|name| name.to_string_lossy().into_owned()),
+            mime_type: Some("application/octet-stream".to_owned()),
+            headers: HeaderMap::new(),
+        })
+    }
+    ///Sets the `filename` the server sees.
+    pub fn file_name(mut self, file_name: &str) -> Self {
+        self.file_name = Some(file_name.to_owned());
+        self
+    }
+    ///Sets the part's `Content-Type`, such as `image/png`.
+    pub fn mime_type(mut self, mime_type: &str) -> Self {
+        self.mime_type = Some(mime_type.to_owned());
+        self
+    }
+    ///Sets a header field of this part, replacing `Content-Disposition` or `Content-Type` if `name` is one of them.
+    ///
+    /// Fields are checked against RFC 9110 by [crate::Request::multipart].
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.insert(name, value);
+        self
+    }
+}
+
+#[cfg(test)] mod test {
+    use super::{Multipart, Part};
+    use crate::Error;
+
+    #[test] fn encoding() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.txt");
+        std::fs::write(&path, b"line 1\nline 2\n").unwrap();
+        let form = Multipart::new()
+            .text("title", "Zo\u{eb}'s \"notes\"")
+            .file("upload", &path).unwrap()
+            .part("raw", Part::bytes(&b"\x00\xff"[..]).file_name("raw.bin").header("Content-Transfer-Encoding", "binary"))
+            .part("icon", Part::bytes(vec![1, 2]).mime_type("image/png").header("content-type", "image/x-icon"));
+        let boundary = form.boundary().to_owned();
+        assert!(boundary.len() > 30 && boundary != Multipart::new().boundary());
+        assert_eq!(form.content_type(), format!("multipart/form-data; boundary={}", boundary));
+        let body = form.into_body().unwrap();
+        assert_eq!(body.as_bytes(), None);
+        let expected = [
+            format!("--{}\r\n", boundary).into_bytes(),
+            "Content-Disposition: form-data; name=\"title\"\r\n\r\nZo\u{eb}'s \"notes\"\r\n".as_bytes().to_vec(),
+            format!("--{}\r\n", boundary).into_bytes(),
+            b"Content-Disposition: form-data; name=\"upload\"; filename=\"notes.txt\"\r\n".to_vec(),
+            b"Content-Type: application/octet-stream\r\n\r\nline 1\nline 2\n\r\n".to_vec(),
+            format!("--{}\r\n", boundary).into_bytes(),
+            b"Content-Disposition: form-data; name=\"raw\"; filename=\"raw.bin\"\r\n".to_vec(),
+            b"Content-Type: application/octet-stream\r\nContent-Transfer-Encoding: binary\r\n\r\n\x00\xff\r\n".to_vec(),
+            format!("--{}\r\n", boundary).into_bytes(),
+            b"Content-Disposition: form-data; name=\"icon\"\r\ncontent-type: image/x-icon\r\n\r\n\x01\x02\r\n".to_vec(),
+            format!("--{}--\r\n", boundary).into_bytes(),
+        ].concat();
+        assert_eq!(body.len(), expected.len() as u64);
+        assert_eq!(body.to_vec().unwrap(), expected);
+    }
+
+    #[test] fn escaping() {
+        let body = Multipart::new().part("a\"b\r\nX-Injected: 1", Part::text("v").file_name("x\".txt")).into_body().unwrap();
+        let text = String::from_utf8(body.to_vec().unwrap()).unwrap();
+        assert!(text.contains("name=\"a%22b%0D%0AX-Injected: 1\"; filename=\"x%22.txt\"\r\n"), "{}", text);
+        //text-only forms are held in memory
+        assert!(body.as_bytes().is_some());
+
+        let invalid = Multipart::new().part("a", Part::text("v").mime_type("text/plain\r\nX-Injected: 1")).into_body();
+        assert!(matches!(invalid, Err(Error::InvalidHeader(name)) if name == "Content-Type"));
+        assert!(matches!(Part::file("/nonexistent/requestr"), Err(Error::Io(_))));
+    }
+}
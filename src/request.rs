@@ -0,0 +1,405 @@
+/*!
+The platform-neutral request builder.
+
+The builder only records what you ask for; a [Transport] does the work in [Request::perform] and [Request::download].
+*/
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use pcore::string::IntoParameterString;
+use pcore::release_pool::ReleasePool;
+use crate::{Error, Response, Downloaded, RetryPolicy, CancelHandle, HeaderMap, Method, Url, IntoUrl, Body, Multipart};
+use crate::transport::{Transport, BoxFuture};
+use crate::retry::Outcome;
+use crate::response::Metadata;
+
+///The `User-Agent` requests are sent with unless told otherwise, the same on every platform.
+pub const DEFAULT_USER_AGENT: &str = concat!("requestr/", env!("CARGO_PKG_VERSION"));
+
+///The `Content-Type` of a body set with [Request::form].
+pub const FORM_CONTENT_TYPE: &str = "application/x-www-form-urlencoded";
+
+///Encodes `pairs` as `application/x-www-form-urlencoded`, as in `a=1&b=x+y`.
+fn form_urlencode<'a, I, K, V>(pairs: I, pool: &ReleasePool) -> String
+where I: IntoIterator<Item=(K,V)>, K: IntoParameterString<'a>, V: IntoParameterString<'a> {
+    let pairs: Vec<String> = pairs.into_iter().map(|(key, value)| {
+        let key = crate::url::form_encode(&key.into_parameter_string(pool).to_string());
+        let value = crate::url::form_encode(&value.into_parameter_string(pool).to_string());
+        format!("{}={}", key, value)
+    }).collect();
+    pairs.join("&")
+}
+
+///A fully-built request, as handed to a [Transport].
+#[derive(Debug,Clone)]
+pub struct RequestDescription {
+    pub(crate) url: Url,
+    pub(crate) headers: HeaderMap,
+    pub(crate) body: Option<Body>,
+    pub(crate) method: Method,
+    pub(crate) file_name: String,
+    pub(crate) timeouts: Timeouts,
+}
+impl RequestDescription {
+    pub fn url(&self) -> &Url { &self.url }
+    pub fn set_url(&mut self, url: Url) { self.url = url }
+    pub fn method(&self) -> &Method { &self.method }
+    ///Header fields, in the order they were set.
+    ///
+    /// Transports send repeated names as one field; see [Request::append_header].
+    pub fn headers(&self) -> &HeaderMap { &self.headers }
+    pub fn headers_mut(&mut self) -> &mut HeaderMap { &mut self.headers }
+    pub fn body(&self) -> Option<&Body> { self.body.as_ref() }
+    ///Name for the file created by [Request::download]
+    pub fn file_name(&self) -> &str { &self.file_name }
+    ///See [Request::timeout].  [Request::perform] and [Request::download] enforce this, so transports may ignore it.
+    pub fn timeout(&self) -> Option<Duration> { self.timeouts.total }
+    ///See [Request::connect_timeout].
+    pub fn connect_timeout(&self) -> Option<Duration> { self.timeouts.connect }
+    ///See [Request::read_idle_timeout].
+    pub fn read_idle_timeout(&self) -> Option<Duration> { self.timeouts.read_idle }
+}
+
+///Limits on how long a request may take.  `None` means no limit.
+#[derive(Debug,Clone,Copy,Default)]
+pub(crate) struct Timeouts {
+    pub total: Option<Duration>,
+    pub connect: Option<Duration>,
+    pub read_idle: Option<Duration>,
+}
+
+pub struct Request {
+    description: RequestDescription,
+    transport: Option<Arc<dyn Transport>>,
+    retry: Option<RetryPolicy>,
+    ///The name of the first header field that was refused, to fail the request with.
+    invalid_header: Option<String>,
+}
+
+impl Request {
+    ///Create a new builder with the given URL, which may be a [Url] or a string to parse as one.
+    ///
+    /// # Errors
+    /// [Error::InvalidURL] if the URL doesn't parse, or its scheme isn't `http` or `https`.  The rules are the same
+    /// on every platform; see [Url::parse].
+    pub fn new<U: IntoUrl>(url: U, pool: &ReleasePool) ->
+    Result<Request,Error> {
+        let url = url.into_url(pool)?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(Error::InvalidURL(url.to_string()));
+        }
+        let file_name = url.file_name().unwrap_or_else(|| "requestsr".to_owned());
+        Ok(Request {
+            description: RequestDescription {
+                url,
+                file_name,
+                headers: [("User-Agent", DEFAULT_USER_AGENT)].into_iter().collect(),
+                body: None,
+                method: Method::Get,
+                timeouts: Timeouts::default(),
+            },
+            transport: None,
+            retry: None,
+            invalid_header: None,
+        })
+    }
+    ///Set (or unset) a header field, replacing any values it had.  Names are compared case-insensitively.
+    ///
+    /// Names and values are checked against RFC 9110 as they're set.  A field that isn't allowed, such as one whose
+    /// value contains a line break, is left out of the [Request::description], and [Request::perform] and
+    /// [Request::download] fail with [Error::InvalidHeader] before anything is sent.
+    pub fn header<'a, K: IntoParameterString<'a>,V:IntoParameterString<'a>>(mut self, key: K,value: Option<V>, pool: &ReleasePool) -> Self {
+        let key = key.into_parameter_string(pool).to_string();
+        match value {
+            Some(v) => {
+                let value = v.into_parameter_string(pool).to_string();
+                if self.check_header(&key, &value) {
+                    self.description.headers.insert(&key, &value)
+                }
+            }
+            None => {self.description.headers.remove(&key);}
+        }
+        self
+    }
+    ///Add a value to a header field, keeping any values it already has.  Fields are checked as for [Request::header].
+    ///
+    /// Every backend sends the values of a repeated name as one field, joined by `, ` (or `; ` for `Cookie`).
+    pub fn append_header<'a, K: IntoParameterString<'a>,V:IntoParameterString<'a>>(mut self, key: K,value: V, pool: &ReleasePool) -> Self {
+        let key = key.into_parameter_string(pool).to_string();
+        let value = value.into_parameter_string(pool).to_string();
+        if self.check_header(&key, &value) {
+            self.description.headers.append(&key, &value);
+        }
+        self
+    }
+    ///Whether a field may be set, remembering the first one that may not.
+    fn check_header(&mut self, name: &str, value: &str) -> bool {
+        let valid = crate::header::validate(name, value).is_ok();
+        if !valid && self.invalid_header.is_none() {
+            self.invalid_header = Some(name.to_owned());
+        }
+        valid
+    }
+    ///Add `key=value` to the URL's query, keeping any query and fragment it already has.
+    ///
+    /// Both are percent-encoded as an HTML form would encode them, with spaces as `+`.  A key may be added more than
+    /// once.
+    pub fn query<'a, K: IntoParameterString<'a>,V:IntoParameterString<'a>>(self, key: K,value: V, pool: &ReleasePool) -> Self {
+        self.query_pairs([(key, value)], pool)
+    }
+    ///Add each of `pairs` to the URL's query, in order.  See [Request::query].
+    pub fn query_pairs<'a, I, K, V>(mut self, pairs: I, pool: &ReleasePool) -> Self
+    where I: IntoIterator<Item=(K,V)>, K: IntoParameterString<'a>, V: IntoParameterString<'a> {
+        self.description.url.append_query(&form_urlencode(pairs, pool));
+        self
+    }
+    ///Add the fields of `query`, such as a struct deriving `Serialize`, to the URL's query.  See [Request::query].
+    ///
+    /// `query` must serialize as a map or as a sequence of pairs, with scalar values.  A slice of pairs may repeat a
+    /// key.  Requires the `serde` feature.
+    ///
+    /// # Errors
+    /// [Error::Serialize] if `query` has another shape, such as a nested struct.
+    #[cfg(feature = "serde")]
+    pub fn serialize_query<T: serde::Serialize + ?Sized>(mut self, query: &T) -> Result<Self,Error> {
+        let pairs = serde_urlencoded::to_string(query).map_err(|e| Error::Serialize(e.to_string()))?;
+        self.description.url.append_query(&pairs);
+        Ok(self)
+    }
+    ///Set (or with `None`, remove) the `User-Agent`.  By default, this is [DEFAULT_USER_AGENT].
+    ///
+    /// Without one, no `User-Agent` is sent on any backend, even `NSURLSession`, which would otherwise add its own.
+    pub fn user_agent<'a, V: IntoParameterString<'a>>(self, user_agent: Option<V>, pool: &ReleasePool) -> Self {
+        self.header("User-Agent", user_agent, pool)
+    }
+    ///Set the HTTP method.  The default is [Method::Get].
+    pub fn method(mut self, method: Method) -> Self {
+        self.description.method = method;
+        self
+    }
+    ///Set the HTTP body data.
+    pub fn body(mut self, body: Box<[u8]>) -> Self {
+        self.description.body = Some(body.into());
+        self
+    }
+    ///Set the body to `pairs`, encoded as an HTML form would encode them (`a=1&b=x+y`), and the `Content-Type` to
+    ///[FORM_CONTENT_TYPE].
+    ///
+    /// Keys may repeat.  The method isn't changed; forms are usually sent with [Method::Post].
+    pub fn form<'a, I, K, V>(self, pairs: I, pool: &ReleasePool) -> Self
+    where I: IntoIterator<Item=(K,V)>, K: IntoParameterString<'a>, V: IntoParameterString<'a> {
+        let body = form_urlencode(pairs, pool);
+        self.form_body(body)
+    }
+    ///Like [Request::form], with the fields of `form`, such as a struct deriving `Serialize`.
+    ///
+    /// `form` must have the shape [Request::serialize_query] requires.  Requires the `serde` feature.
+    ///
+    /// # Errors
+    /// [Error::Serialize] if `form` has another shape, such as a nested struct.
+    #[cfg(feature = "serde")]
+    pub fn serialize_form<T: serde::Serialize + ?Sized>(self, form: &T) -> Result<Self,Error> {
+        let body = serde_urlencoded::to_string(form).map_err(|e| Error::Serialize(e.to_string()))?;
+        Ok(self.form_body(body))
+    }
+    ///Set the body to `form`, and the `Content-Type` to `multipart/form-data` with the form's boundary.
+    ///
+    /// File parts are read while the request is being sent; see [Multipart].
+    ///
+    /// # Errors
+    /// [Error::InvalidHeader] if a part has a header field RFC 9110 doesn't allow, such as a MIME type containing a
+    /// line break.
+    pub fn multipart(mut self, form: Multipart) -> Result<Self,Error> {
+        self.description.headers.insert("Content-Type", &form.content_type());
+        self.description.body = Some(form.into_body()?);
+        Ok(self)
+    }
+    fn form_body(mut self, body: String) -> Self {
+        self.description.headers.insert("Content-Type", FORM_CONTENT_TYPE);
+        self.body(body.into_bytes().into_boxed_slice())
+    }
+
+    ///Choose the transport that will carry out this request.
+    ///
+    /// By default, this is [crate::transport::default_transport].
+    pub fn transport(mut self, transport: Arc<dyn Transport>) -> Self {
+        self.transport = Some(transport);
+        self
+    }
+    ///Limit how long [Request::perform] or [Request::download] may take in all, including any retries and reading
+    /// the body.  Past the limit, the request is cancelled and fails with [Error::Timeout].
+    ///
+    /// By default, there is no limit.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.description.timeouts.total = Some(timeout);
+        self
+    }
+    ///Limit how long establishing a connection may take, failing with [Error::Timeout].
+    ///
+    /// `HttpClient` on Windows has no such setting, so there only [Request::timeout] applies.  On macOS,
+    /// `NSURLSession` has a single idle timeout, which is set to the shorter of this and [Request::read_idle_timeout].
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.description.timeouts.connect = Some(timeout);
+        self
+    }
+    ///Limit how long the server may go without sending anything, failing with [Error::Timeout].
+    ///
+    /// This catches a stalled transfer without limiting a large download that is making progress.  Not supported by
+    /// `HttpClient` on Windows.  libcurl checks roughly once a second, so short limits may be overshot.
+    pub fn read_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.description.timeouts.read_idle = Some(timeout);
+        self
+    }
+    ///Re-send the request when it fails in a way that may be transient.  See [RetryPolicy].
+    ///
+    /// By default, requests are sent once.
+    pub fn retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = Some(policy);
+        self
+    }
+    ///The request as it will be handed to the transport.
+    pub fn description(&self) -> &RequestDescription {
+        &self.description
+    }
+    fn into_parts(self) -> (Arc<dyn Transport>, RequestDescription, Option<RetryPolicy>, Option<String>) {
+        (self.transport.unwrap_or_else(crate::transport::default_transport), self.description, self.retry, self.invalid_header)
+    }
+
+    ///Performs the request, reading the response body into memory.
+    ///
+    /// Dropping the future cancels the request, including any retries.
+    pub fn perform(self, _pool: &ReleasePool) -> impl Future<Output=Result<Response,Error>> {
+        self.send(None, |transport, description| transport.perform(description))
+    }
+    ///Like [Request::perform], but also returns a handle to cancel the request from elsewhere.
+    pub fn perform_cancellable(self, _pool: &ReleasePool) -> (impl Future<Output=Result<Response,Error>>, CancelHandle) {
+        let handle = CancelHandle::new();
+        (self.send(Some(handle.clone()), |transport, description| transport.perform(description)), handle)
+    }
+
+    ///Downloads the request into a file.
+    ///
+    /// The file will be located in a temporary directory and will be deleted when the return value is dropped.
+    /// Dropping the future cancels the request, including any retries.
+    pub fn download(self, _pool: &ReleasePool) -> impl Future<Output=Result<Downloaded,Error>> {
+        self.send(None, |transport, description| transport.download(description))
+    }
+    ///Like [Request::download], but also returns a handle to cancel the request from elsewhere.
+    pub fn download_cancellable(self, _pool: &ReleasePool) -> (impl Future<Output=Result<Downloaded,Error>>, CancelHandle) {
+        let handle = CancelHandle::new();
+        (self.send(Some(handle.clone()), |transport, description| transport.download(description)), handle)
+    }
+
+    ///Sends the request with `send`, applying the retry policy, the cancel handle and the overall timeout.
+    fn send<T: Outcome>(self, cancel: Option<CancelHandle>, send: fn(&dyn Transport, RequestDescription) -> BoxFuture<Result<T,Error>>) -> impl Future<Output=Result<T,Error>> {
+        let (transport, description, retry, invalid_header) = self.into_parts();
+        let url = description.url.to_string();
+        let total = description.timeouts.total;
+        async move {
+            if let Some(name) = invalid_header {
+                return Err(Error::InvalidHeader(name));
+            }
+            let attempts = crate::retry::run(retry.as_ref(), description, |d| send(transport.as_ref(), d));
+            let attempts = crate::cancel::cancellable(cancel, attempts);
+            let (mut outcome, attempts) = crate::timer::timeout(total, attempts).await?;
+            let metadata = outcome.metadata_mut();
+            metadata.attempts = attempts;
+            fill_url(metadata, url);
+            Ok(outcome)
+        }
+    }
+}
+
+///Transports that don't report a final URL get the requested one.
+fn fill_url(metadata: &mut Metadata, url: String) {
+    if metadata.url.is_empty() {
+        metadata.url = url;
+    }
+}
+
+#[cfg(test)] mod test {
+    use crate::{Request, Response, Downloaded, Error, Method, StatusCode, Url};
+    use pcore::pstr;
+    use pcore::release_pool::autoreleasepool;
+    use pcore::release_pool::ReleasePool;
+    use std::future::Future;
+    use std::path::PathBuf;
+
+    ///The same user code must build on every platform.  This is only compiled, never run.
+    #[allow(dead_code)]
+    fn api(pool: &ReleasePool) -> Result<(),Error> {
+        let request: Request = Request::new(pstr!("https://example.com/file.txt"), pool)?
+            .header(pstr!("Accept"), Some(pstr!("text/plain")), pool)
+            .method(Method::Post)
+            .body(Box::new([1,2,3]))
+            .timeout(std::time::Duration::from_secs(30))
+            .connect_timeout(std::time::Duration::from_secs(5))
+            .read_idle_timeout(std::time::Duration::from_secs(10));
+        //requests can be awaited on multithreaded executors
+        let _perform: &(dyn Future<Output=Result<Response,Error>> + Send) = &request.perform(pool);
+        let base = Url::parse("https://example.com/dir/")?;
+        let request = Request::new(base.join("file.txt")?, pool)?;
+        let _download: &(dyn Future<Output=Result<Downloaded,Error>> + Send) = &request.download(pool);
+        let request = Request::new(pstr!("https://example.com/file.txt"), pool)?;
+        let (_perform, handle) = request.perform_cancellable(pool);
+        let _send: &dyn Send = &handle;
+        handle.clone().cancel();
+        Ok(())
+    }
+    #[test] fn invalid_url() {
+        autoreleasepool(|pool| {
+            for url in ["example.com/file.txt", "ftp://example.com/file.txt", "https://exa mple.com/"] {
+                assert!(matches!(Request::new(url, pool), Err(Error::InvalidURL(_))), "{:?}", url);
+            }
+            let request = Request::new("https://example.com/a%20b.txt?q", pool).unwrap();
+            assert_eq!(request.description().url().path(), "/a%20b.txt");
+        });
+    }
+    #[test] fn query() {
+        autoreleasepool(|pool| {
+            let request = Request::new("https://example.com/search?lang=en#results", pool).unwrap()
+                .query(pstr!("q"), pstr!("rust & C++"), pool)
+                .query_pairs([("tag", "a"), ("tag", "b/c")], pool);
+            assert_eq!(request.description().url(), "https://example.com/search?lang=en&q=rust+%26+C%2B%2B&tag=a&tag=b%2Fc#results");
+        });
+    }
+    #[test] fn form() {
+        autoreleasepool(|pool| {
+            let request = Request::new("https://example.com/", pool).unwrap()
+                .header(pstr!("Content-Type"), Some(pstr!("text/plain")), pool)
+                .form([("name", "Zoë O'Brien"), ("tags", "a+b"), ("tags", "")], pool);
+            let description = request.description();
+            assert_eq!(description.body().and_then(|b| b.as_bytes()), Some(&b"name=Zo%C3%AB+O%27Brien&tags=a%2Bb&tags="[..]));
+            assert_eq!(description.headers().get_all("content-type").collect::<Vec<_>>(), [crate::request::FORM_CONTENT_TYPE]);
+        });
+    }
+    #[cfg(feature = "serde")]
+    #[test] fn serialize_form() {
+        autoreleasepool(|pool| {
+            let request = Request::new("https://example.com/", pool).unwrap()
+                .serialize_form(&[("a", "1"), ("b", "x y")]).unwrap();
+            assert_eq!(request.description().body().and_then(|b| b.as_bytes()), Some(&b"a=1&b=x+y"[..]));
+            assert_eq!(request.description().headers().get("Content-Type"), Some(crate::request::FORM_CONTENT_TYPE));
+        });
+    }
+    #[cfg(feature = "serde")]
+    #[test] fn serialize_query() {
+        autoreleasepool(|pool| {
+            let request = Request::new("https://example.com/", pool).unwrap()
+                .serialize_query(&[("page", "2"), ("tag", "a b"), ("tag", "c")]).unwrap();
+            assert_eq!(request.description().url().query(), Some("page=2&tag=a+b&tag=c"));
+            let nested = Request::new("https://example.com/", pool).unwrap().serialize_query(&[("a", ("b", "c"))]);
+            assert!(matches!(nested, Err(Error::Serialize(_))));
+        });
+    }
+    #[allow(dead_code)]
+    fn response(response: &Response) -> Result<&[u8],(StatusCode,&[u8])> {
+        let _all: &[u8] = response.data().as_slice();
+        response.check_status().map(|d| d.as_slice()).map_err(|(code, d)| (code, d.as_slice()))
+    }
+    #[allow(dead_code)]
+    fn downloaded(downloaded: &Downloaded) -> Result<PathBuf,Error> {
+        downloaded.check_status()?;
+        Ok(downloaded.copy_path())
+    }
+}
@@ -0,0 +1,298 @@
+/*!
+Platform-neutral response types.
+
+Every backend produces these same types, so code that inspects a response builds unchanged on every platform.
+*/
+use std::fmt::{Debug, Formatter};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+use crate::{Error, StatusCode};
+use crate::header::HeaderMap;
+
+enum Buffer {
+    Bytes(Box<[u8]>),
+    #[cfg(target_os = "macos")]
+    Platform(crate::macos::response::Buffer),
+    #[cfg(target_os = "windows")]
+    Platform(crate::windows::response::Buffer),
+}
+
+///An opaque data type, may wrap a platform-specific buffer
+pub struct Data(Buffer);
+impl Data {
+    pub fn as_slice(&self) -> &[u8] {
+        match &self.0 {
+            Buffer::Bytes(bytes) => bytes,
+            #[cfg(any(target_os = "macos", target_os = "windows"))]
+            Buffer::Platform(buffer) => buffer.as_slice(),
+        }
+    }
+}
+impl Debug for Data {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("Data({} bytes)", self.as_slice().len()))
+    }
+}
+impl From<Vec<u8>> for Data {
+    fn from(bytes: Vec<u8>) -> Self {
+        Data(Buffer::Bytes(bytes.into_boxed_slice()))
+    }
+}
+#[cfg(target_os = "macos")]
+impl From<crate::macos::response::Buffer> for Data {
+    fn from(buffer: crate::macos::response::Buffer) -> Self {
+        Data(Buffer::Platform(buffer))
+    }
+}
+#[cfg(target_os = "windows")]
+impl From<crate::windows::response::Buffer> for Data {
+    fn from(buffer: crate::windows::response::Buffer) -> Self {
+        Data(Buffer::Platform(buffer))
+    }
+}
+
+///The HTTP version a response was received over.
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash)]
+#[non_exhaustive]
+pub enum Version {
+    Http10,
+    Http11,
+    Http2,
+    Http3,
+}
+impl Version {
+    ///Parses the version as it appears in an HTTP/1 status line, e.g. `HTTP/1.1` or `HTTP/2`.
+    pub(crate) fn parse(version: &str) -> Option<Version> {
+        match version {
+            "HTTP/1.0" => Some(Version::Http10),
+            "HTTP/1.1" => Some(Version::Http11),
+            "HTTP/2" | "HTTP/2.0" => Some(Version::Http2),
+            "HTTP/3" | "HTTP/3.0" => Some(Version::Http3),
+            _ => None,
+        }
+    }
+}
+impl std::fmt::Display for Version {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Version::Http10 => "HTTP/1.0",
+            Version::Http11 => "HTTP/1.1",
+            Version::Http2 => "HTTP/2",
+            Version::Http3 => "HTTP/3",
+        })
+    }
+}
+
+///Everything about a response except its body.
+#[derive(Debug,Clone)]
+pub(crate) struct Metadata {
+    pub code: StatusCode,
+    ///The reason phrase sent by the server, if the protocol and backend report one
+    pub reason: Option<String>,
+    pub version: Option<Version>,
+    ///The final URL, after redirects.  Empty if the transport didn't say.
+    pub url: String,
+    pub headers: HeaderMap,
+    ///How many times the request was sent.  0 if it didn't go through [crate::Request].
+    pub attempts: u32,
+}
+impl Default for Metadata {
+    fn default() -> Self {
+        Metadata::new(StatusCode::OK)
+    }
+}
+impl Metadata {
+    pub fn new(code: StatusCode) -> Metadata {
+        Metadata { code, reason: None, version: None, url: String::new(), headers: HeaderMap::new(), attempts: 0 }
+    }
+    fn reason(&self) -> &str {
+        self.reason.as_deref().unwrap_or_else(|| self.code.canonical_reason().unwrap_or(""))
+    }
+    fn retry_after(&self) -> Option<Duration> {
+        match self.code.as_u16() {
+            429 | 503 => crate::retry::retry_after(&self.headers, SystemTime::now()),
+            _ => None,
+        }
+    }
+}
+
+///The response to [crate::Request::perform].
+///
+/// The body has been read in full by the time you get one of these.
+#[derive(Debug)]
+pub struct Response{
+    metadata: Metadata,
+    data: Data,
+}
+impl Response {
+    ///Creates a response with the given status code and body.
+    ///
+    /// Useful when implementing a [crate::transport::Transport].  [Response::url] is filled in with the request's URL
+    /// unless the transport sets it with [Response::with_url].
+    pub fn new(code: StatusCode, data: Data) -> Response {
+        Response::from_metadata(Metadata::new(code), data)
+    }
+    pub(crate) fn from_metadata(metadata: Metadata, data: Data) -> Response {
+        Response { metadata, data }
+    }
+    pub(crate) fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+    pub(crate) fn metadata_mut(&mut self) -> &mut Metadata {
+        &mut self.metadata
+    }
+    ///Sets the response header fields.
+    pub fn with_headers(mut self, headers: HeaderMap) -> Response {
+        self.metadata.headers = headers;
+        self
+    }
+    ///Sets the reason phrase.
+    pub fn with_reason(mut self, reason: &str) -> Response {
+        self.metadata.reason = Some(reason.to_owned());
+        self
+    }
+    ///Sets the HTTP version.
+    pub fn with_version(mut self, version: Version) -> Response {
+        self.metadata.version = Some(version);
+        self
+    }
+    ///Sets the final URL.
+    pub fn with_url(mut self, url: &str) -> Response {
+        self.metadata.url = url.to_owned();
+        self
+    }
+    ///The HTTP status code.
+    pub fn status(&self) -> StatusCode {
+        self.metadata.code
+    }
+    ///The reason phrase sent with the status code.
+    ///
+    /// HTTP/2 and some backends don't report one; in that case this is the standard phrase for [Response::status],
+    /// or `""` for a nonstandard code.
+    pub fn reason(&self) -> &str {
+        self.metadata.reason()
+    }
+    ///The HTTP version of the response, if the backend reports it.
+    pub fn version(&self) -> Option<Version> {
+        self.metadata.version
+    }
+    ///The URL the response came from, after following any redirects.
+    pub fn url(&self) -> &str {
+        &self.metadata.url
+    }
+    ///The response header fields.
+    pub fn headers(&self) -> &HeaderMap {
+        &self.metadata.headers
+    }
+    ///The first value of the header field `name`, compared case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.metadata.headers.get(name)
+    }
+    ///The response body, regardless of status.
+    pub fn data(&self) -> &Data {
+        &self.data
+    }
+    ///How many times the request was sent, counting the first.  More than 1 only under a [crate::RetryPolicy].
+    pub fn attempts(&self) -> u32 {
+        self.metadata.attempts.max(1)
+    }
+    ///For 429 Too Many Requests or 503 Service Unavailable, how long the server asked us to wait before trying again.
+    ///
+    /// Read from `Retry-After`, as seconds or an HTTP-date, or else from `RateLimit`, `RateLimit-Reset` or
+    /// `X-RateLimit-Reset`.  `None` for other statuses, or if the server didn't say.
+    pub fn retry_after(&self) -> Option<Duration> {
+        self.metadata.retry_after()
+    }
+    ///Whether the status says the same request may succeed later: 408, 425, 429, or any 5xx.
+    ///
+    /// Like [Error::is_retryable], this doesn't consider whether the request is safe to repeat.
+    pub fn is_retryable_status(&self) -> bool {
+        self.metadata.code.is_retryable()
+    }
+    ///Converts to a result that models success or error based on http status codes.
+    ///
+    /// If HTTP code suggests 'success', returns Ok(data).
+    /// Otherwise, returns Err(statusCode,data).
+    pub fn check_status(&self) -> Result<&Data, (StatusCode, &Data)> {
+        if self.metadata.code.is_success() {
+            Ok(self.data())
+        }
+        else {
+            Err((self.metadata.code,self.data()))
+        }
+    }
+}
+
+///The response to [crate::Request::download].
+///
+/// The file lives in a temporary directory, which is deleted when this is dropped.
+#[derive(Debug)]
+pub struct Downloaded{
+    _tempdir: tempfile::TempDir,
+    pathbuf: PathBuf,
+    metadata: Metadata,
+}
+impl Downloaded {
+    ///The path of the downloaded file.  The file name is the last path component of the URL.
+    pub fn copy_path(&self) -> PathBuf { self.pathbuf.clone() }
+    pub(crate) fn new(dir: tempfile::TempDir, path_buf: PathBuf, metadata: Metadata) -> Self {
+        Self {
+            _tempdir: dir,
+            pathbuf: path_buf,
+            metadata,
+        }
+    }
+    pub(crate) fn metadata_mut(&mut self) -> &mut Metadata {
+        &mut self.metadata
+    }
+    ///The HTTP status code.
+    pub fn status(&self) -> StatusCode {
+        self.metadata.code
+    }
+    ///The reason phrase sent with the status code.  See [Response::reason].
+    pub fn reason(&self) -> &str {
+        self.metadata.reason()
+    }
+    ///The HTTP version of the response, if the backend reports it.
+    pub fn version(&self) -> Option<Version> {
+        self.metadata.version
+    }
+    ///The URL the file came from, after following any redirects.
+    pub fn url(&self) -> &str {
+        &self.metadata.url
+    }
+    ///The response header fields.
+    pub fn headers(&self) -> &HeaderMap {
+        &self.metadata.headers
+    }
+    ///The first value of the header field `name`, compared case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.metadata.headers.get(name)
+    }
+    ///How many times the request was sent.  See [Response::attempts].
+    pub fn attempts(&self) -> u32 {
+        self.metadata.attempts.max(1)
+    }
+    ///How long the server asked us to wait before trying again.  See [Response::retry_after].
+    pub fn retry_after(&self) -> Option<Duration> {
+        self.metadata.retry_after()
+    }
+    ///Whether the status says the same request may succeed later.  See [Response::is_retryable_status].
+    pub fn is_retryable_status(&self) -> bool {
+        self.metadata.code.is_retryable()
+    }
+    ///Converts to a result that models success or error based on http status codes.
+    ///
+    /// If HTTP code suggests 'success', returns Ok(()).
+    /// If the server is rate limiting us (429, or 503 with a delay), returns Err(Error::RateLimited).
+    /// Otherwise, returns Err(Error::StatusCode).
+    pub fn check_status(&self) -> Result<(),Error> {
+        let code = self.metadata.code;
+        match self.retry_after() {
+            _ if code.is_success() => Ok(()),
+            Some(delay) => Err(Error::RateLimited { status: code, retry_after: Some(delay) }),
+            None if code == 429 => Err(Error::RateLimited { status: code, retry_after: None }),
+            None => Err(Error::StatusCode(code)),
+        }
+    }
+}
@@ -0,0 +1,448 @@
+/*!
+Retrying failed requests.
+
+A [RetryPolicy] set with [crate::Request::retry] re-sends the request when it fails in a way that may be
+transient, waiting longer after each attempt.  The body is kept by the [crate::Request], so every attempt sends
+the same bytes.
+
+When a server answers 429 or 503 and says how long to wait, with `Retry-After` or rate-limit headers, the policy
+waits that long instead.  See [crate::Response::retry_after].
+
+```
+use std::time::Duration;
+use requestr::{Request, RetryPolicy};
+use pcore::pstr;
+# pcore::release_pool::autoreleasepool(|pool| {
+let request = Request::new(pstr!("https://example.com/api"), pool).unwrap()
+    .retry(RetryPolicy::new().max_attempts(5).base_delay(Duration::from_millis(250)));
+# });
+```
+*/
+use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crate::{Error, Response, Downloaded, Method, StatusCode};
+use crate::header::HeaderMap;
+use crate::response::Metadata;
+use crate::request::RequestDescription;
+use crate::transport::BoxFuture;
+
+type ErrorPredicate = Arc<dyn Fn(&Error) -> bool + Send + Sync>;
+type StatusPredicate = Arc<dyn Fn(StatusCode) -> bool + Send + Sync>;
+
+///When and how often to re-send a request.
+///
+/// By default, a request is sent at most 3 times.  The wait after attempt `n` is `base_delay * 2^(n-1)`, capped at
+/// `max_delay`, and then shortened by a random fraction of up to `jitter` so that many clients don't retry in step.
+/// Errors are retried if [Error::is_retryable], responses if [StatusCode::is_retryable].
+///
+/// Only methods that RFC 9110 defines as idempotent ([Method::is_idempotent]) are retried unless you opt in with [RetryPolicy::non_idempotent].
+///
+/// If the server asks for a delay ([crate::Response::retry_after]), the policy waits exactly that long.  A delay
+/// longer than `max_delay` ends the retries, so the caller can decide.
+#[derive(Clone)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    base_delay: Duration,
+    max_delay: Duration,
+    jitter: f64,
+    retry_error: ErrorPredicate,
+    retry_status: StatusPredicate,
+    non_idempotent: bool,
+    honor_retry_after: bool,
+}
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(10),
+            jitter: 0.5,
+            retry_error: Arc::new(Error::is_retryable),
+            retry_status: Arc::new(StatusCode::is_retryable),
+            non_idempotent: false,
+            honor_retry_after: true,
+        }
+    }
+}
+impl RetryPolicy {
+    pub fn new() -> Self {
+        RetryPolicy::default()
+    }
+    ///The most times the request is sent, counting the first.  1 disables retries.
+    pub fn max_attempts(mut self, attempts: u32) -> Self {
+        self.max_attempts = attempts.max(1);
+        self
+    }
+    ///The wait after the first attempt, before jitter.
+    pub fn base_delay(mut self, delay: Duration) -> Self {
+        self.base_delay = delay;
+        self
+    }
+    ///The longest wait between attempts, before jitter.
+    pub fn max_delay(mut self, delay: Duration) -> Self {
+        self.max_delay = delay;
+        self
+    }
+    ///The largest fraction, from 0 to 1, that a wait may be randomly shortened by.  0 makes waits exact.
+    pub fn jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+    ///Chooses which errors are retried, instead of [Error::is_retryable].
+    pub fn retry_errors<F: Fn(&Error) -> bool + Send + Sync + 'static>(mut self, predicate: F) -> Self {
+        self.retry_error = Arc::new(predicate);
+        self
+    }
+    ///Chooses which status codes are retried, instead of [StatusCode::is_retryable].
+    pub fn retry_statuses<F: Fn(StatusCode) -> bool + Send + Sync + 'static>(mut self, predicate: F) -> Self {
+        self.retry_status = Arc::new(predicate);
+        self
+    }
+    ///Whether to retry methods that aren't idempotent, such as `POST`.
+    ///
+    /// Only turn this on if the server can tell a repeated request from a new one, for example with an
+    /// idempotency key.
+    pub fn non_idempotent(mut self, retry: bool) -> Self {
+        self.non_idempotent = retry;
+        self
+    }
+    ///Whether to wait as long as the server asks with `Retry-After` or rate-limit headers.  On by default; when off,
+    /// those responses get the usual backoff.
+    pub fn honor_retry_after(mut self, honor: bool) -> Self {
+        self.honor_retry_after = honor;
+        self
+    }
+
+    fn allows_method(&self, method: &Method) -> bool {
+        self.non_idempotent || method.is_idempotent()
+    }
+    ///The wait after `attempt` (counting from 1), before jitter.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.base_delay.saturating_mul(factor).min(self.max_delay)
+    }
+    fn delay(&self, attempt: u32) -> Duration {
+        self.backoff(attempt).mul_f64(1.0 - self.jitter * random())
+    }
+}
+impl Debug for RetryPolicy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RetryPolicy")
+            .field("max_attempts", &self.max_attempts)
+            .field("base_delay", &self.base_delay)
+            .field("max_delay", &self.max_delay)
+            .field("jitter", &self.jitter)
+            .field("non_idempotent", &self.non_idempotent)
+            .field("honor_retry_after", &self.honor_retry_after)
+            .finish_non_exhaustive()
+    }
+}
+
+///A number in `0..1`.  Jitter only needs to differ between clients, not to be unpredictable.
+fn random() -> f64 {
+    use std::hash::{BuildHasher, Hasher};
+    use std::sync::atomic::{AtomicU64, Ordering};
+    static CALLS: AtomicU64 = AtomicU64::new(0);
+    //each RandomState is seeded differently; the time and a count vary the input as well
+    let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+    hasher.write_u128(SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos());
+    hasher.write_u64(CALLS.fetch_add(1, Ordering::Relaxed));
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+///What [run] needs to know about a successful attempt.
+pub(crate) trait Outcome {
+    fn status(&self) -> StatusCode;
+    fn retry_after(&self) -> Option<Duration>;
+    fn metadata_mut(&mut self) -> &mut Metadata;
+}
+impl Outcome for Response {
+    fn status(&self) -> StatusCode { Response::status(self) }
+    fn retry_after(&self) -> Option<Duration> { Response::retry_after(self) }
+    fn metadata_mut(&mut self) -> &mut Metadata { Response::metadata_mut(self) }
+}
+impl Outcome for Downloaded {
+    fn status(&self) -> StatusCode { Downloaded::status(self) }
+    fn retry_after(&self) -> Option<Duration> { Downloaded::retry_after(self) }
+    fn metadata_mut(&mut self) -> &mut Metadata { Downloaded::metadata_mut(self) }
+}
+
+///Sends `description` with `perform` until it succeeds, `policy` says to stop, or the attempts run out.
+///
+/// Returns the outcome with the number of attempts.  An error is returned as the last attempt failed, with the
+/// number of attempts recorded by [Error::set_attempts].
+pub(crate) async fn run<T: Outcome, P>(policy: Option<&RetryPolicy>, description: RequestDescription, mut perform: P) -> Result<(T, u32),Error>
+where P: FnMut(RequestDescription) -> BoxFuture<Result<T,Error>> {
+    let policy = policy.filter(|p| p.allows_method(&description.method));
+    let max_attempts = policy.map_or(1, |p| p.max_attempts);
+    let mut description = Some(description);
+    let mut attempt = 1;
+    loop {
+        //the last attempt can have the original
+        let request = if attempt < max_attempts { description.clone() } else { description.take() };
+        let result = perform(request.expect("no attempts left")).await;
+        let delay = policy.filter(|_| attempt < max_attempts).and_then(|policy| {
+            let (retry, asked) = match &result {
+                Ok(t) => ((policy.retry_status)(t.status()), t.retry_after()),
+                Err(e) => ((policy.retry_error)(e), match e { Error::RateLimited { retry_after, .. } => *retry_after, _ => None }),
+            };
+            match asked.filter(|_| policy.honor_retry_after) {
+                _ if !retry => None,
+                Some(asked) if asked > policy.max_delay => None,
+                Some(asked) => Some(asked),
+                None => Some(policy.delay(attempt)),
+            }
+        });
+        match (delay, result) {
+            (Some(delay), _) => {
+                crate::timer::Delay::new(delay).await;
+                attempt += 1;
+            }
+            (None, Ok(t)) => return Ok((t, attempt)),
+            (None, Err(mut error)) => {
+                error.set_attempts(attempt);
+                return Err(error)
+            }
+        }
+    }
+}
+
+///How long the server asked us to wait before trying again.
+///
+/// `Retry-After` may be delta-seconds or an HTTP-date (RFC 9110 §10.2.3).  Failing that, the reset time of a
+/// rate limit is used: `RateLimit: ..., reset=N` (or `t=N`), `RateLimit-Reset: N`, or `X-RateLimit-Reset`, which some
+/// servers send as a Unix timestamp instead of seconds.  A time in the past is no wait at all.
+pub(crate) fn retry_after(headers: &HeaderMap, now: SystemTime) -> Option<Duration> {
+    let until = |time: SystemTime| time.duration_since(now).unwrap_or(Duration::ZERO);
+    if let Some(value) = headers.get("Retry-After") {
+        let value = value.trim();
+        return match value.parse::<u64>() {
+            Ok(seconds) => Some(Duration::from_secs(seconds)),
+            Err(_) => http_date(value).map(until),
+        };
+    }
+    let reset = headers.get("RateLimit").and_then(|value| {
+        value.split([',', ';']).find_map(|param| match param.trim().split_once('=') {
+            Some(("reset" | "t", seconds)) => seconds.trim().parse::<u64>().ok(),
+            _ => None,
+        })
+    });
+    if let Some(seconds) = reset.or_else(|| headers.get("RateLimit-Reset").and_then(|v| v.trim().parse().ok())) {
+        return Some(Duration::from_secs(seconds));
+    }
+    let seconds: u64 = headers.get("X-RateLimit-Reset")?.trim().parse().ok()?;
+    //a delay of more than 30 years is a timestamp
+    if seconds > 1_000_000_000 {
+        Some(until(UNIX_EPOCH + Duration::from_secs(seconds)))
+    }
+    else {
+        Some(Duration::from_secs(seconds))
+    }
+}
+
+///Parses the three date formats of RFC 9110 §5.6.7:
+/// `Sun, 06 Nov 1994 08:49:37 GMT`, `Sunday, 06-Nov-94 08:49:37 GMT`, and `Sun Nov  6 08:49:37 1994`.
+fn http_date(value: &str) -> Option<SystemTime> {
+    const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+    let month = |name: &str| MONTHS.iter().position(|m| *m == name).map(|m| m as u64 + 1);
+    let mut parts = value.split([' ', ',', '-']).filter(|p| !p.is_empty()).skip(1);
+    let (day, month, year, time) = match (parts.next()?, parts.next()?, parts.next()?, parts.next()?) {
+        (name, day, time, year) if month(name).is_some() => (day, month(name)?, year, time),
+        (day, name, year, time) => (day, month(name)?, year, time),
+    };
+    let day: u64 = day.parse().ok()?;
+    let year = match year.parse::<u64>().ok()? {
+        year @ 0..=69 => year + 2000,
+        year @ 70..=99 => year + 1900,
+        year => year,
+    };
+    let mut time = time.split(':').map(|t| t.parse::<u64>().ok());
+    let (hour, minute, second) = (time.next()??, time.next()??, time.next()??);
+    if !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 || year < 1970 {
+        return None;
+    }
+    //days since 1970-01-01 in the proleptic Gregorian calendar
+    let (y, m) = if month <= 2 { (year - 1, month + 9) } else { (year, month - 3) };
+    let era = y / 400;
+    let year_of_era = y % 400;
+    let day_of_year = (153 * m + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = (era * 146097 + day_of_era).checked_sub(719468)?;
+    Some(UNIX_EPOCH + Duration::from_secs(days * 86400 + hour * 3600 + minute * 60 + second))
+}
+
+#[cfg(test)] mod test {
+    use super::{RetryPolicy, retry_after, http_date};
+    use crate::{Request, Response, Error, PlatformError, HeaderMap, Method, StatusCode};
+    use crate::mock::{MockTransport, Matcher};
+    use pcore::pstr;
+    use pcore::release_pool::autoreleasepool;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy::new().base_delay(Duration::from_millis(1)).jitter(0.0)
+    }
+    ///Fails with `error` until the `succeed_on`th request.
+    fn flaky(succeed_on: u32, error: fn() -> Error) -> Arc<MockTransport> {
+        let mock = Arc::new(MockTransport::new());
+        let count = AtomicU32::new(0);
+        mock.on_with(Matcher::any(), move |_| {
+            if count.fetch_add(1, Ordering::SeqCst) + 1 >= succeed_on { Ok(Response::new(StatusCode::OK, b"ok".to_vec().into())) }
+            else { Err(error()) }
+        });
+        mock
+    }
+    fn connect() -> Error {
+        Error::Connect(PlatformError::message("refused"))
+    }
+
+    #[test] fn backoff() {
+        let policy = RetryPolicy::new().base_delay(Duration::from_millis(100)).max_delay(Duration::from_millis(300)).jitter(0.0);
+        let delays: Vec<_> = (1..=4).map(|a| policy.delay(a)).collect();
+        assert_eq!(delays, [100, 200, 300, 300].map(Duration::from_millis));
+        let jittered = RetryPolicy::new().jitter(1.0);
+        for _ in 0..100 {
+            assert!(jittered.delay(1) <= Duration::from_millis(100));
+        }
+    }
+
+    #[test] fn retries_until_success() {
+        autoreleasepool(|pool| {
+            let mock = flaky(3, connect);
+            let future = Request::new(pstr!("https://example.com/"), pool).unwrap()
+                .body(Box::new(*b"same"))
+                .method(Method::Put)
+                .retry(policy())
+                .transport(mock.clone())
+                .perform(pool);
+            let response = kiruna::test::test_await(future, Duration::from_secs(1)).unwrap();
+            assert_eq!(response.attempts(), 3);
+            mock.assert_calls(&Matcher::any(), 3);
+            assert!(mock.requests().iter().all(|r| r.body().and_then(|b| b.as_bytes()) == Some(&b"same"[..])));
+        });
+    }
+
+    #[test] fn gives_up() {
+        autoreleasepool(|pool| {
+            let mock = flaky(10, connect);
+            let future = Request::new(pstr!("https://example.com/"), pool).unwrap()
+                .retry(policy().max_attempts(4))
+                .transport(mock.clone())
+                .perform(pool);
+            let error = kiruna::test::test_await(future, Duration::from_secs(1)).unwrap_err();
+            //the last attempt's error, as it is
+            assert!(matches!(&error, Error::Connect(e) if e.attempts() == 4), "{:?}", error);
+            assert_eq!(error.attempts(), 4);
+            assert_eq!(error.to_string(), "couldn't connect: refused (after 4 attempts)");
+
+            //errors that won't go away are returned as they are
+            let mock = flaky(10, || Error::Dns(PlatformError::message("no such host")));
+            let future = Request::new(pstr!("https://example.com/"), pool).unwrap()
+                .retry(policy())
+                .transport(mock.clone())
+                .perform(pool);
+            let error = kiruna::test::test_await(future, Duration::from_secs(1)).unwrap_err();
+            assert!(matches!(error, Error::Dns(_)));
+            assert_eq!(error.attempts(), 1);
+        });
+    }
+
+    #[test] fn statuses() {
+        autoreleasepool(|pool| {
+            let mock = Arc::new(MockTransport::new());
+            mock.on(Matcher::any(), StatusCode::SERVICE_UNAVAILABLE, b"busy");
+            let future = Request::new(pstr!("https://example.com/file"), pool).unwrap()
+                .retry(policy())
+                .transport(mock.clone())
+                .download(pool);
+            let downloaded = kiruna::test::test_await(future, Duration::from_secs(1)).unwrap();
+            assert_eq!(downloaded.status(), 503);
+            assert_eq!(downloaded.attempts(), 3);
+            //the count is on the response; the error made from its status doesn't know it
+            let error = downloaded.check_status().unwrap_err();
+            assert!(matches!(error, Error::StatusCode(s) if s == 503));
+            assert_eq!(error.attempts(), 1);
+
+            let future = Request::new(pstr!("https://example.com/file"), pool).unwrap()
+                .retry(policy().retry_statuses(|_| false))
+                .transport(mock.clone())
+                .perform(pool);
+            let response = kiruna::test::test_await(future, Duration::from_secs(1)).unwrap();
+            assert_eq!(response.attempts(), 1);
+            mock.assert_calls(&Matcher::any(), 4);
+        });
+    }
+
+    #[test] fn idempotent_only() {
+        autoreleasepool(|pool| {
+            let mock = flaky(2, connect);
+            let future = Request::new(pstr!("https://example.com/"), pool).unwrap()
+                .method(Method::Post)
+                .retry(policy())
+                .transport(mock.clone())
+                .perform(pool);
+            assert!(kiruna::test::test_await(future, Duration::from_secs(1)).unwrap_err().is_connect());
+
+            let future = Request::new(pstr!("https://example.com/"), pool).unwrap()
+                .method(Method::Post)
+                .retry(policy().non_idempotent(true))
+                .transport(mock.clone())
+                .perform(pool);
+            assert_eq!(kiruna::test::test_await(future, Duration::from_secs(1)).unwrap().attempts(), 1);
+            mock.assert_calls(&Matcher::any(), 2);
+        });
+    }
+
+    #[test] fn retry_after_headers() {
+        let now = UNIX_EPOCH + Duration::from_secs(784111777);
+        let parse = |name: &str, value: &str| retry_after(&[(name, value)].into_iter().collect::<HeaderMap>(), now);
+        assert_eq!(http_date("Sun, 06 Nov 1994 08:49:37 GMT"), Some(now));
+        assert_eq!(http_date("Sunday, 06-Nov-94 08:49:37 GMT"), Some(now));
+        assert_eq!(http_date("Sun Nov  6 08:49:37 1994"), Some(now));
+        assert_eq!(http_date("Thu, 29 Feb 2024 00:00:00 GMT"), Some(UNIX_EPOCH + Duration::from_secs(1709164800)));
+        assert_eq!(http_date("yesterday"), None);
+
+        assert_eq!(parse("Retry-After", "120"), Some(Duration::from_secs(120)));
+        assert_eq!(parse("retry-after", "Sun, 06 Nov 1994 08:50:07 GMT"), Some(Duration::from_secs(30)));
+        assert_eq!(parse("Retry-After", "Sun, 06 Nov 1994 08:00:00 GMT"), Some(Duration::ZERO));
+        assert_eq!(parse("Retry-After", "soon"), None);
+        assert_eq!(parse("RateLimit", "limit=100, remaining=0, reset=50"), Some(Duration::from_secs(50)));
+        assert_eq!(parse("RateLimit", "\"default\";r=0;t=7"), Some(Duration::from_secs(7)));
+        assert_eq!(parse("RateLimit-Reset", "9"), Some(Duration::from_secs(9)));
+        let later = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let timestamp: HeaderMap = [("X-RateLimit-Reset", "1700000020")].into_iter().collect();
+        assert_eq!(retry_after(&timestamp, later), Some(Duration::from_secs(20)));
+        assert_eq!(parse("X-RateLimit-Reset", "60"), Some(Duration::from_secs(60)));
+        assert_eq!(parse("ETag", "1"), None);
+    }
+
+    #[test] fn rate_limited() {
+        autoreleasepool(|pool| {
+            let mock = Arc::new(MockTransport::new());
+            let count = AtomicU32::new(0);
+            mock.on_with(Matcher::any(), move |_| Ok(match count.fetch_add(1, Ordering::SeqCst) {
+                0 => Response::new(StatusCode::TOO_MANY_REQUESTS, Vec::new().into()).with_headers([("Retry-After", "0")].into_iter().collect()),
+                1 => Response::new(StatusCode::SERVICE_UNAVAILABLE, Vec::new().into()).with_headers([("RateLimit-Reset", "3600")].into_iter().collect()),
+                _ => Response::new(StatusCode::OK, Vec::new().into()),
+            }));
+            //the second wait is longer than max_delay, so the 503 is the answer
+            let future = Request::new(pstr!("https://example.com/"), pool).unwrap()
+                .retry(policy().base_delay(Duration::from_secs(60)))
+                .transport(mock.clone())
+                .download(pool);
+            let downloaded = kiruna::test::test_await(future, Duration::from_secs(1)).unwrap();
+            assert_eq!(downloaded.attempts(), 2);
+            assert_eq!(downloaded.retry_after(), Some(Duration::from_secs(3600)));
+            assert!(matches!(downloaded.check_status(), Err(Error::RateLimited { status, retry_after: Some(d) }) if status == 503 && d.as_secs() == 3600));
+
+            let future = Request::new(pstr!("https://example.com/"), pool).unwrap()
+                .transport(mock.clone())
+                .perform(pool);
+            let response = kiruna::test::test_await(future, Duration::from_secs(1)).unwrap();
+            assert_eq!(response.retry_after(), None);
+        });
+    }
+}
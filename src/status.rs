@@ -0,0 +1,160 @@
+/*!
+Response status codes.
+*/
+use std::fmt::{Display, Formatter};
+
+///An HTTP status code, such as 200 or 404.
+///
+/// Compares equal to the `u16` it holds, so `response.status() == 404` works.
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash,PartialOrd,Ord)]
+pub struct StatusCode(u16);
+impl StatusCode {
+    pub const OK: StatusCode = StatusCode(200);
+    pub const CREATED: StatusCode = StatusCode(201);
+    pub const NO_CONTENT: StatusCode = StatusCode(204);
+    pub const MOVED_PERMANENTLY: StatusCode = StatusCode(301);
+    pub const FOUND: StatusCode = StatusCode(302);
+    pub const NOT_MODIFIED: StatusCode = StatusCode(304);
+    pub const BAD_REQUEST: StatusCode = StatusCode(400);
+    pub const UNAUTHORIZED: StatusCode = StatusCode(401);
+    pub const FORBIDDEN: StatusCode = StatusCode(403);
+    pub const NOT_FOUND: StatusCode = StatusCode(404);
+    pub const TOO_MANY_REQUESTS: StatusCode = StatusCode(429);
+    pub const INTERNAL_SERVER_ERROR: StatusCode = StatusCode(500);
+    pub const SERVICE_UNAVAILABLE: StatusCode = StatusCode(503);
+
+    ///The status `code`, or `None` unless it has three digits (100 to 999), as RFC 9110 requires.
+    pub const fn new(code: u16) -> Option<StatusCode> {
+        match code {
+            100..=999 => Some(StatusCode(code)),
+            _ => None,
+        }
+    }
+    ///A code reported by the platform's HTTP stack, which has already parsed the status line.
+    #[cfg_attr(not(any(target_os = "macos", target_os = "windows", target_os = "linux")), allow(dead_code))]
+    pub(crate) const fn from_platform(code: u16) -> StatusCode {
+        StatusCode(code)
+    }
+    pub const fn as_u16(self) -> u16 {
+        self.0
+    }
+    ///1xx
+    pub const fn is_informational(self) -> bool {
+        self.0 >= 100 && self.0 <= 199
+    }
+    ///2xx
+    pub const fn is_success(self) -> bool {
+        self.0 >= 200 && self.0 <= 299
+    }
+    ///3xx
+    pub const fn is_redirect(self) -> bool {
+        self.0 >= 300 && self.0 <= 399
+    }
+    ///4xx
+    pub const fn is_client_error(self) -> bool {
+        self.0 >= 400 && self.0 <= 499
+    }
+    ///5xx
+    pub const fn is_server_error(self) -> bool {
+        self.0 >= 500 && self.0 <= 599
+    }
+    ///Whether the status says the same request may succeed later: 408 Request Timeout, 425 Too Early,
+    ///429 Too Many Requests, or any 5xx.
+    pub const fn is_retryable(self) -> bool {
+        matches!(self.0, 408 | 425 | 429 | 500..=599)
+    }
+    ///The standard reason phrase, such as `Not Found`, or `None` for a nonstandard code.
+    pub const fn canonical_reason(self) -> Option<&'static str> {
+        Some(match self.0 {
+            100 => "Continue",
+            101 => "Switching Protocols",
+            200 => "OK",
+            201 => "Created",
+            202 => "Accepted",
+            203 => "Non-Authoritative Information",
+            204 => "No Content",
+            205 => "Reset Content",
+            206 => "Partial Content",
+            300 => "Multiple Choices",
+            301 => "Moved Permanently",
+            302 => "Found",
+            303 => "See Other",
+            304 => "Not Modified",
+            307 => "Temporary Redirect",
+            308 => "Permanent Redirect",
+            400 => "Bad Request",
+            401 => "Unauthorized",
+            403 => "Forbidden",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            406 => "Not Acceptable",
+            408 => "Request Timeout",
+            409 => "Conflict",
+            410 => "Gone",
+            411 => "Length Required",
+            412 => "Precondition Failed",
+            413 => "Content Too Large",
+            415 => "Unsupported Media Type",
+            416 => "Range Not Satisfiable",
+            422 => "Unprocessable Content",
+            425 => "Too Early",
+            428 => "Precondition Required",
+            429 => "Too Many Requests",
+            500 => "Internal Server Error",
+            501 => "Not Implemented",
+            502 => "Bad Gateway",
+            503 => "Service Unavailable",
+            504 => "Gateway Timeout",
+            505 => "HTTP Version Not Supported",
+            _ => return None,
+        })
+    }
+}
+///The code and its standard reason phrase, such as `404 Not Found`.
+impl Display for StatusCode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.canonical_reason() {
+            Some(reason) => write!(f, "{} {}", self.0, reason),
+            None => write!(f, "{}", self.0),
+        }
+    }
+}
+impl From<StatusCode> for u16 {
+    fn from(status: StatusCode) -> u16 {
+        status.0
+    }
+}
+impl PartialEq<u16> for StatusCode {
+    fn eq(&self, other: &u16) -> bool {
+        self.0 == *other
+    }
+}
+impl PartialEq<StatusCode> for u16 {
+    fn eq(&self, other: &StatusCode) -> bool {
+        *self == other.0
+    }
+}
+
+#[cfg(test)] mod test {
+    use super::StatusCode;
+
+    #[test] fn classes() {
+        assert_eq!(StatusCode::new(99), None);
+        assert_eq!(StatusCode::new(1000), None);
+        let teapot = StatusCode::new(418).unwrap();
+        assert!(teapot.is_client_error() && !teapot.is_success() && !teapot.is_server_error());
+        assert!(StatusCode::new(103).unwrap().is_informational());
+        assert!(StatusCode::NO_CONTENT.is_success());
+        assert!(StatusCode::NOT_MODIFIED.is_redirect());
+        assert!(StatusCode::SERVICE_UNAVAILABLE.is_server_error() && StatusCode::SERVICE_UNAVAILABLE.is_retryable());
+        assert!(!StatusCode::NOT_FOUND.is_retryable());
+        assert_eq!(StatusCode::NOT_FOUND, 404);
+        assert_eq!(u16::from(StatusCode::OK), 200);
+    }
+
+    #[test] fn display() {
+        assert_eq!(StatusCode::NOT_FOUND.to_string(), "404 Not Found");
+        assert_eq!(StatusCode::new(599).unwrap().to_string(), "599");
+        assert_eq!(StatusCode::new(599).unwrap().canonical_reason(), None);
+    }
+}
@@ -0,0 +1,8 @@
+/*!
+A fallback backend that speaks plain HTTP/1.1 over `std::net::TcpStream`.
+
+Enabled with the `std-backend` feature.
+*/
+mod request;
+mod http;
+pub use request::StdTransport;
@@ -0,0 +1,464 @@
+/*!
+A minimal HTTP/1.1 client over `std::net::TcpStream`.
+
+Supports `Content-Length`, chunked and close-delimited bodies, redirects, and keeps idle
+connections alive for reuse.  There is no TLS, so only `http://` URLs are supported.
+*/
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use crate::blocking::{Cancellation, HookId};
+use crate::{Error, PlatformError, HeaderMap, Method, StatusCode, Url, Body};
+use crate::response::{Metadata, Version};
+use crate::request::Timeouts;
+use crate::client::SessionConfig;
+
+///Matches the limit used by NSURLSession and libcurl's default.
+const MAX_REDIRECTS: usize = 20;
+///Maximum number of idle connections kept around.
+const MAX_IDLE: usize = 16;
+
+fn invalid_data(message: &str) -> Error {
+    Error::BodyRead(std::io::Error::new(ErrorKind::InvalidData, message.to_owned()).into())
+}
+///For failures while sending the request.
+fn connection_error(e: std::io::Error) -> Error {
+    match e.kind() {
+        ErrorKind::TimedOut | ErrorKind::WouldBlock => Error::Timeout(e.into()),
+        _ => Error::Connect(e.into()),
+    }
+}
+///For failures while reading the response.
+fn read_error(e: std::io::Error) -> Error {
+    match e.kind() {
+        ErrorKind::TimedOut | ErrorKind::WouldBlock => Error::Timeout(e.into()),
+        _ => Error::BodyRead(e.into()),
+    }
+}
+
+///The parts of an `http://` URL we need to make a request.
+#[derive(Debug,PartialEq)]
+pub(crate) struct Target {
+    url: Url,
+    ///`host:port`, used for connecting and as the `Host` header
+    authority: String,
+    ///path and query
+    path: String,
+}
+impl Target {
+    pub fn parse(url: &str) -> Result<Target,Error> {
+        Target::new(Url::parse(url)?)
+    }
+    pub fn new(url: Url) -> Result<Target,Error> {
+        //user information isn't sent; there's no TLS either
+        if url.scheme() != "http" || !url.username().is_empty() {
+            return Err(Error::InvalidURL(url.to_string()));
+        }
+        let authority = format!("{}:{}", url.host(), url.port_or_known_default().unwrap());
+        let path = url.request_target();
+        Ok(Target{url, authority, path})
+    }
+    ///The URL of this target, without any fragment.
+    fn url(&self) -> String {
+        let url = self.url.as_str();
+        url.split('#').next().unwrap().to_owned()
+    }
+    ///Resolves a `Location` header against this target.
+    fn redirect(&self, location: &str) -> Result<Target,Error> {
+        Target::new(self.url.join(location)?)
+    }
+}
+
+///Header fields dropped when a redirect leads to another host.  `Host` is set for the new one.
+const CREDENTIALS: [&str; 4] = ["Authorization", "Proxy-Authorization", "Cookie", "Host"];
+
+///Header fields dropped when a redirect turns the request into a `GET` without a body.
+const BODY_FIELDS: [&str; 3] = ["Content-Type", "Content-Encoding", "Content-Length"];
+
+///A connection pool and the options that go with it.
+pub(crate) struct Session {
+    follow_redirects: bool,
+    proxy: Option<Target>,
+    ///Idle keep-alive connections, keyed by authority.
+    idle: Mutex<Vec<(String, TcpStream)>>,
+}
+impl Session {
+    pub fn new(config: &SessionConfig) -> Result<Session,Error> {
+        Ok(Session {
+            follow_redirects: config.follow_redirects,
+            proxy: config.proxy.as_deref().map(Target::parse).transpose()?,
+            idle: Mutex::new(Vec::new()),
+        })
+    }
+}
+
+///A request ready to go on the wire.
+pub(crate) struct Outgoing<'a> {
+    pub method: &'a Method,
+    pub url: &'a Url,
+    pub headers: &'a HeaderMap,
+    pub body: Option<&'a Body>,
+    pub timeouts: Timeouts,
+}
+
+///Performs the request, following redirects, and writes the final body into `sink`.
+///
+/// Returns the final response's head.
+pub(crate) fn send(session: &Arc<Session>, request: Outgoing, sink: &mut dyn Write, cancellation: &Cancellation) -> Result<Metadata,Error> {
+    let mut target = Target::new(request.url.clone())?;
+    let mut url = target.url();
+    let mut method = request.method.clone();
+    let mut body = request.body;
+    let mut headers = request.headers.clone();
+    let origin = target.authority.clone();
+    for _ in 0..=MAX_REDIRECTS {
+        let mut head = exchange(session, &target, &method, &headers, body, &request.timeouts, cancellation)?;
+        let location = head.header("location").map(|l| l.to_owned());
+        match (head.status.as_u16(), location) {
+            (status @ (301 | 302 | 303 | 307 | 308), Some(location)) if session.follow_redirects => {
+                head.finish(&mut std::io::sink())?;
+                target = target.redirect(&location)?;
+                url = target.url();
+                //like libcurl, credentials aren't sent on to another host
+                if target.authority != origin {
+                    for name in CREDENTIALS {
+                        headers.remove(name);
+                    }
+                }
+                //like browsers, 301 and 302 only turn POST into GET, and 303 turns anything but HEAD into GET
+                let to_get = match status {
+                    301 | 302 => method == Method::Post,
+                    303 => method != Method::Head,
+                    _ => false,
+                };
+                if to_get {
+                    method = Method::Get;
+                    body = None;
+                    //the fields that described the body go with it
+                    for name in BODY_FIELDS {
+                        headers.remove(name);
+                    }
+                }
+            }
+            _ => {
+                let metadata = Metadata {
+                    code: head.status,
+                    reason: Some(std::mem::take(&mut head.reason)),
+                    version: Version::parse(&head.version),
+                    url,
+                    headers: std::mem::take(&mut head.headers).into_iter().collect(),
+                    ..Metadata::default()
+                };
+                head.finish(sink)?;
+                return Ok(metadata);
+            }
+        }
+    }
+    Err(Error::TooManyRedirects(PlatformError::message(&format!("more than {} redirects", MAX_REDIRECTS))))
+}
+
+///A connection plus where it came from, so it can be returned to the pool.
+struct Connection {
+    session: Arc<Session>,
+    authority: String,
+    reader: BufReader<TcpStream>,
+    reused: bool,
+    cancellation: Cancellation,
+    ///Shuts the socket down on cancel.  Removed before the socket goes back to the pool, where another request may
+    /// take it.
+    shutdown: Option<HookId>,
+}
+impl Connection {
+    ///Opens a connection to `authority`, preferring an idle one from the pool if `pooled` is set.
+    fn open(session: &Arc<Session>, authority: &str, pooled: bool, timeouts: &Timeouts, cancellation: &Cancellation) -> Result<Connection,Error> {
+        let pooled = if pooled {
+            let mut idle = session.idle.lock().unwrap();
+            idle.iter().position(|c| c.0 == authority).map(|index| idle.remove(index).1)
+        }
+        else {
+            None
+        };
+        let (stream, reused) = match pooled {
+            Some(stream) => (stream, true),
+            None => {
+                let addresses: Vec<_> = authority.to_socket_addrs().map_err(|e| Error::Dns(e.into()))?.collect();
+                (connect(&addresses, timeouts.connect).map_err(connection_error)?, false)
+            }
+        };
+        //pooled connections may have been opened with other timeouts
+        stream.set_read_timeout(timeouts.read_idle).map_err(connection_error)?;
+        stream.set_write_timeout(timeouts.read_idle).map_err(connection_error)?;
+        let shutdown = match stream.try_clone() {
+            Ok(shutdown) => cancellation.on_cancel(move || {
+                let _ = shutdown.shutdown(Shutdown::Both);
+            }),
+            Err(_) => None,
+        };
+        Ok(Connection{session: session.clone(), authority: authority.to_owned(), reader: BufReader::new(stream), reused, cancellation: cancellation.clone(), shutdown})
+    }
+    fn recycle(self) {
+        //leftover bytes mean we've lost track of the framing
+        if !self.reader.buffer().is_empty() {
+            return;
+        }
+        //a cancelled request may have shut the socket down, or be about to
+        if let Some(shutdown) = self.shutdown {
+            if !self.cancellation.remove_hook(shutdown) {
+                return;
+            }
+        }
+        else if self.cancellation.is_cancelled() {
+            return;
+        }
+        let mut idle = self.session.idle.lock().unwrap();
+        if idle.len() >= MAX_IDLE {
+            idle.remove(0);
+        }
+        idle.push((self.authority, self.reader.into_inner()));
+    }
+}
+
+///Like `TcpStream::connect`, tries each address in turn, but gives up on each one after `timeout`.
+fn connect(addresses: &[SocketAddr], timeout: Option<Duration>) -> std::io::Result<TcpStream> {
+    let Some(timeout) = timeout else {
+        return TcpStream::connect(addresses);
+    };
+    let mut last_error = std::io::Error::new(ErrorKind::NotFound, "no addresses to connect to");
+    for address in addresses {
+        match TcpStream::connect_timeout(address, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = e,
+        }
+    }
+    Err(last_error)
+}
+
+///How the body of a response is delimited.
+#[derive(Debug,PartialEq)]
+enum Framing {
+    Empty,
+    Length(u64),
+    Chunked,
+    Close,
+}
+
+///A response whose head has been read, but whose body has not.
+struct Head {
+    status: StatusCode,
+    version: String,
+    reason: String,
+    headers: Vec<(String, String)>,
+    framing: Framing,
+    keep_alive: bool,
+    connection: Connection,
+}
+impl Head {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|h| h.0.eq_ignore_ascii_case(name)).map(|h| h.1.as_str())
+    }
+    ///Reads the body into `sink`, then returns the connection to the pool if possible.
+    fn finish(self, sink: &mut dyn Write) -> Result<(),Error> {
+        let mut connection = self.connection;
+        let reader = &mut connection.reader;
+        match self.framing {
+            Framing::Empty => {}
+            Framing::Length(length) => {
+                let copied = std::io::copy(&mut reader.take(length), sink).map_err(read_error)?;
+                if copied != length {
+                    return Err(read_error(ErrorKind::UnexpectedEof.into()));
+                }
+            }
+            Framing::Chunked => read_chunked(reader, sink)?,
+            Framing::Close => {
+                std::io::copy(reader, sink).map_err(read_error)?;
+            }
+        }
+        if self.keep_alive && self.framing != Framing::Close {
+            connection.recycle();
+        }
+        Ok(())
+    }
+}
+
+///Sends one request and reads the response head.
+///
+/// If a pooled connection turns out to have been closed by the server, retries on a fresh one.
+fn exchange(session: &Arc<Session>, target: &Target, method: &Method, headers: &HeaderMap, body: Option<&Body>, timeouts: &Timeouts, cancellation: &Cancellation) -> Result<Head,Error> {
+    //a proxy is sent the whole URL
+    let (authority, request_target) = match &session.proxy {
+        Some(proxy) => (&proxy.authority, target.url()),
+        None => (&target.authority, target.path.clone()),
+    };
+    let outgoing = |connection: &mut Connection| write_request(connection, target, &request_target, method, headers, body);
+    let mut connection = Connection::open(session, authority, true, timeouts, cancellation)?;
+    match outgoing(&mut connection).and_then(|_| read_status_line(&mut connection)) {
+        Ok(status_line) => read_head(connection, status_line, method),
+        //a server that is merely slow would be just as slow on a fresh connection
+        Err(e) if connection.reused && !e.is_timeout() && !cancellation.is_cancelled() => {
+            let mut connection = Connection::open(session, authority, false, timeouts, cancellation)?;
+            outgoing(&mut connection)?;
+            let status_line = read_status_line(&mut connection)?;
+            read_head(connection, status_line, method)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+fn write_request(connection: &mut Connection, target: &Target, request_target: &str, method: &Method, headers: &HeaderMap, body: Option<&Body>) -> Result<(),Error> {
+    let mut head = format!("{} {} HTTP/1.1\r\n", method, request_target);
+    if !headers.contains("host") {
+        head.push_str(&format!("Host: {}\r\n", target.authority.strip_suffix(":80").unwrap_or(&target.authority)));
+    }
+    for (key, value) in headers.combined() {
+        //we always frame the body ourselves
+        if key.eq_ignore_ascii_case("content-length") || key.eq_ignore_ascii_case("transfer-encoding") {
+            continue;
+        }
+        head.push_str(&format!("{}: {}\r\n", key, value));
+    }
+    match body {
+        Some(body) => head.push_str(&format!("Content-Length: {}\r\n", body.len())),
+        None if matches!(method, Method::Post | Method::Put | Method::Patch) => head.push_str("Content-Length: 0\r\n"),
+        None => {}
+    }
+    head.push_str("\r\n");
+    let stream = connection.reader.get_mut();
+    stream.write_all(head.as_bytes()).map_err(connection_error)?;
+    match body.map(|b| (b, b.as_bytes())) {
+        None => {}
+        Some((_, Some(bytes))) => stream.write_all(bytes).map_err(connection_error)?,
+        //streamed from files, which fail with Error::Io rather than as a lost connection
+        Some((body, None)) => {
+            let mut reader = body.reader();
+            let mut buffer = vec![0; 64 * 1024];
+            loop {
+                let read = reader.read(&mut buffer).map_err(Error::Io)?;
+                if read == 0 {
+                    break;
+                }
+                stream.write_all(&buffer[..read]).map_err(connection_error)?;
+            }
+        }
+    }
+    stream.flush().map_err(connection_error)
+}
+
+fn read_line(reader: &mut BufReader<TcpStream>) -> Result<String,Error> {
+    let mut line = String::new();
+    if reader.read_line(&mut line).map_err(read_error)? == 0 {
+        return Err(read_error(ErrorKind::UnexpectedEof.into()));
+    }
+    Ok(line.trim_end_matches(['\r', '\n']).to_owned())
+}
+
+///Reads the status line, skipping any interim (1xx) responses.
+fn read_status_line(connection: &mut Connection) -> Result<StatusLine,Error> {
+    loop {
+        //a connection that closes before responding was lost, rather than sending a bad response
+        let line = read_line(&mut connection.reader).map_err(|e| match e {
+            Error::BodyRead(e) => Error::Connect(e),
+            e => e,
+        })?;
+        let mut parts = line.splitn(3, ' ');
+        let version = parts.next().unwrap().to_owned();
+        if !version.starts_with("HTTP/1.") {
+            return Err(invalid_data("Not an HTTP/1.x response"));
+        }
+        let status = parts.next().and_then(|s| s.parse().ok()).and_then(StatusCode::new).ok_or_else(|| invalid_data("Invalid status code"))?;
+        if status.is_informational() && status != 101 {
+            //discard the interim response's headers
+            while !read_line(&mut connection.reader)?.is_empty() {}
+            continue;
+        }
+        let reason = parts.next().unwrap_or("").to_owned();
+        return Ok(StatusLine{version, status, reason});
+    }
+}
+
+struct StatusLine {
+    version: String,
+    status: StatusCode,
+    reason: String,
+}
+
+fn read_head(mut connection: Connection, status_line: StatusLine, method: &Method) -> Result<Head,Error> {
+    let StatusLine{version, status, reason} = status_line;
+    let mut headers = Vec::new();
+    loop {
+        let line = read_line(&mut connection.reader)?;
+        if line.is_empty() { break }
+        let (key, value) = line.split_once(':').ok_or_else(|| invalid_data("Invalid header line"))?;
+        headers.push((key.trim().to_owned(), value.trim().to_owned()));
+    }
+    let chunked = has_token(&headers, "transfer-encoding", "chunked");
+    let framing = if *method == Method::Head || status == 204 || status == 304 {
+        Framing::Empty
+    }
+    else if chunked {
+        Framing::Chunked
+    }
+    else if let Some(length) = headers.iter().find(|h| h.0.eq_ignore_ascii_case("content-length")) {
+        Framing::Length(length.1.parse().map_err(|_| invalid_data("Invalid Content-Length"))?)
+    }
+    else {
+        Framing::Close
+    };
+    let keep_alive = if version == "HTTP/1.0" {
+        has_token(&headers, "connection", "keep-alive")
+    }
+    else {
+        !has_token(&headers, "connection", "close")
+    };
+    Ok(Head{status, version, reason, headers, framing, keep_alive, connection})
+}
+
+///Whether any `name` header contains `token` in its comma-separated list.
+fn has_token(headers: &[(String, String)], name: &str, token: &str) -> bool {
+    headers.iter()
+        .filter(|h| h.0.eq_ignore_ascii_case(name))
+        .any(|h| h.1.split(',').any(|t| t.trim().eq_ignore_ascii_case(token)))
+}
+
+///Decodes a chunked body into `sink`, discarding any trailers.
+fn read_chunked(reader: &mut BufReader<TcpStream>, sink: &mut dyn Write) -> Result<(),Error> {
+    loop {
+        let line = read_line(reader)?;
+        //ignore chunk extensions
+        let size = line.split(';').next().unwrap().trim();
+        let size = u64::from_str_radix(size, 16).map_err(|_| invalid_data("Invalid chunk size"))?;
+        if size == 0 {
+            while !read_line(reader)?.is_empty() {}
+            return Ok(());
+        }
+        let copied = std::io::copy(&mut reader.take(size), sink).map_err(read_error)?;
+        if copied != size {
+            return Err(read_error(ErrorKind::UnexpectedEof.into()));
+        }
+        if !read_line(reader)?.is_empty() {
+            return Err(invalid_data("Missing CRLF after chunk"));
+        }
+    }
+}
+
+#[cfg(test)] mod test {
+    use super::Target;
+    #[test] fn parse() {
+        let target = Target::parse("http://example.com").unwrap();
+        assert_eq!((target.authority.as_str(), target.path.as_str()), ("example.com:80", "/"));
+        let target = Target::parse("http://127.0.0.1:8080/a/b?c=d#e").unwrap();
+        assert_eq!((target.authority.as_str(), target.path.as_str()), ("127.0.0.1:8080", "/a/b?c=d"));
+        assert_eq!(target.url(), "http://127.0.0.1:8080/a/b?c=d");
+        assert_eq!(Target::parse("HTTP://example.com?q").unwrap().path, "/?q");
+        assert!(Target::parse("https://example.com").is_err());
+        assert!(Target::parse("example.com").is_err());
+        assert!(Target::parse("http://user@example.com").is_err());
+    }
+    #[test] fn redirect() {
+        let base = Target::parse("http://example.com/a/b?c").unwrap();
+        assert_eq!(base.redirect("/x").unwrap().path, "/x");
+        assert_eq!(base.redirect("x").unwrap().path, "/a/x");
+        assert_eq!(base.redirect("http://other:81/y").unwrap().authority, "other:81");
+        assert_eq!(base.redirect("//other/y").unwrap().authority, "other:80");
+    }
+}
@@ -0,0 +1,238 @@
+use crate::{Error, Response, Downloaded};
+use crate::request::RequestDescription;
+use crate::transport::{Transport, BoxFuture};
+use super::http::{self, Outgoing, Session};
+use crate::client::SessionConfig;
+use std::sync::Arc;
+use std::fs::File;
+use std::future::Future;
+use std::io::{BufWriter, Write};
+use tempfile::tempdir;
+
+fn perform(session: Arc<Session>, request: RequestDescription) -> impl Future<Output=Result<Response,Error>> {
+    crate::blocking::spawn(move |cancellation| {
+        let mut data = Vec::new();
+        let outgoing = Outgoing{method: &request.method, url: &request.url, headers: &request.headers, body: request.body.as_ref(), timeouts: request.timeouts};
+        let metadata = http::send(&session, outgoing, &mut data, &cancellation)?;
+        Ok(Response::from_metadata(metadata, data.into()))
+    })
+}
+
+///Downloads the request into a file.
+///
+/// The file will be located in a temporary directory and will be deleted when the return value is dropped.
+fn download(session: Arc<Session>, request: RequestDescription) -> impl Future<Output=Result<Downloaded,Error>> {
+    crate::blocking::spawn(move |cancellation| {
+        let dir = tempdir().map_err(Error::Io)?;
+        let path = dir.path().join(&request.file_name);
+        let mut file = BufWriter::new(File::create(&path).map_err(Error::Io)?);
+        let outgoing = Outgoing{method: &request.method, url: &request.url, headers: &request.headers, body: request.body.as_ref(), timeouts: request.timeouts};
+        let metadata = http::send(&session, outgoing, &mut file, &cancellation)?;
+        file.flush().map_err(Error::Io)?;
+        Ok(Downloaded::new(dir, path, metadata))
+    })
+}
+
+///Performs requests with a plain HTTP/1.1 client built on `std::net`.
+///
+/// This is the default transport on targets without a supported OS HTTP stack.  Elsewhere, choose it with
+/// [crate::Request::transport].  Since it does not wrap an OS stack, there is no TLS: only `http://` URLs are supported.
+///
+/// Each `StdTransport` keeps its own pool of idle connections.
+pub struct StdTransport {
+    session: Arc<Session>,
+}
+impl StdTransport {
+    pub fn new() -> Self {
+        StdTransport { session: Arc::new(Session::new(&SessionConfig::default()).expect("default session")) }
+    }
+    //only the default where the OS has no stack of its own
+    #[cfg_attr(any(target_os = "macos", target_os = "windows", target_os = "linux"), allow(dead_code))]
+    pub(crate) fn with_session(config: &SessionConfig) -> Result<Self,Error> {
+        Ok(StdTransport { session: Arc::new(Session::new(config)?) })
+    }
+}
+impl Default for StdTransport {
+    fn default() -> Self {
+        StdTransport::new()
+    }
+}
+impl Transport for StdTransport {
+    fn perform(&self, request: RequestDescription) -> BoxFuture<Result<Response,Error>> {
+        Box::pin(perform(self.session.clone(), request))
+    }
+    fn download(&self, request: RequestDescription) -> BoxFuture<Result<Downloaded,Error>> {
+        Box::pin(download(self.session.clone(), request))
+    }
+}
+
+#[cfg(test)] mod test {
+    use super::StdTransport;
+    use crate::client::SessionConfig;
+    use crate::{Request, Method};
+    use std::sync::Arc;
+    use pcore::pstr;
+    use pcore::release_pool::autoreleasepool;
+    use crate::test_support::serve;
+    use std::time::Duration;
+
+    #[test] fn content_length() {
+        let (url, server) = serve(&["HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello"]);
+        autoreleasepool(|pool| {
+            let request = Request::new(url, pool).unwrap()
+                .header(pstr!("Accept"),Some(pstr!("application/vnd.github.v3+json")), pool);
+            let future = request.transport(Arc::new(StdTransport::new())).perform(pool);
+            let response = kiruna::test::test_await(future, Duration::from_secs(10)).unwrap();
+            assert_eq!(response.check_status().unwrap().as_slice(), b"hello");
+        });
+        let request = &server.join().unwrap()[0];
+        assert!(request.starts_with("GET / HTTP/1.1\r\n"));
+        assert!(request.contains("Accept: application/vnd.github.v3+json\r\n"));
+    }
+
+    #[test] fn chunked() {
+        let (url, server) = serve(&["HTTP/1.1 201 Created\r\nTransfer-Encoding: chunked\r\n\r\n5;ext=1\r\nhello\r\n7\r\n, world\r\n0\r\nTrailer: x\r\n\r\n"]);
+        autoreleasepool(|pool| {
+            let request = Request::new(url, pool).unwrap()
+                .method(Method::Post)
+                .body(Box::new(*b"payload"));
+            let future = request.transport(Arc::new(StdTransport::new())).perform(pool);
+            let response = kiruna::test::test_await(future, Duration::from_secs(10)).unwrap();
+            assert_eq!(response.check_status().unwrap().as_slice(), b"hello, world");
+        });
+        let request = &server.join().unwrap()[0];
+        assert!(request.starts_with("POST / HTTP/1.1\r\n"));
+        assert!(request.contains("Content-Length: 7\r\n"));
+        assert!(request.ends_with("\r\n\r\npayload"));
+    }
+
+    #[test] fn keep_alive() {
+        //the server only accepts one connection, so the second request must reuse it
+        let (url, server) = serve(&[
+            "HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nfirst",
+            "HTTP/1.1 404 Not Found\r\nContent-Length: 6\r\n\r\nsecond",
+        ]);
+        autoreleasepool(|pool| {
+            let transport = Arc::new(StdTransport::new());
+            let future = Request::new(url.clone(), pool).unwrap().transport(transport.clone()).perform(pool);
+            let response = kiruna::test::test_await(future, Duration::from_secs(10)).unwrap();
+            assert_eq!(response.check_status().unwrap().as_slice(), b"first");
+            let future = Request::new(url, pool).unwrap().transport(transport).perform(pool);
+            let response = kiruna::test::test_await(future, Duration::from_secs(10)).unwrap();
+            let (code, data) = response.check_status().unwrap_err();
+            assert_eq!(code, 404);
+            assert_eq!(data.as_slice(), b"second");
+        });
+        assert_eq!(server.join().unwrap().len(), 2);
+    }
+
+    #[test] fn redirect() {
+        let (url, server) = serve(&[
+            "HTTP/1.1 303 See Other\r\nLocation: /elsewhere\r\nContent-Length: 0\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nConnection: close\r\n\r\nuntil close",
+        ]);
+        autoreleasepool(|pool| {
+            let request = Request::new(url, pool).unwrap()
+                .method(Method::Post)
+                .body(Box::new(*b"payload"));
+            let future = request.transport(Arc::new(StdTransport::new())).perform(pool);
+            let response = kiruna::test::test_await(future, Duration::from_secs(10)).unwrap();
+            assert_eq!(response.check_status().unwrap().as_slice(), b"until close");
+        });
+        let requests = server.join().unwrap();
+        assert!(requests[1].starts_with("GET /elsewhere HTTP/1.1\r\n"));
+    }
+
+    #[test] fn redirect_methods() {
+        let (url, server) = serve(&[
+            "HTTP/1.1 301 Moved Permanently\r\nLocation: /put\r\nContent-Length: 0\r\n\r\n",
+            "HTTP/1.1 302 Found\r\nLocation: /post\r\nContent-Length: 0\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n",
+            "HTTP/1.1 303 See Other\r\nLocation: /delete\r\nContent-Length: 0\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nConnection: close\r\n\r\n",
+        ]);
+        autoreleasepool(|pool| {
+            let transport = Arc::new(StdTransport::new());
+            let future = Request::new(url.clone(), pool).unwrap()
+                .method(Method::Put)
+                .body(Box::new(*b"put"))
+                .transport(transport.clone())
+                .perform(pool);
+            kiruna::test::test_await(future, Duration::from_secs(10)).unwrap();
+            let future = Request::new(url, pool).unwrap()
+                .method(Method::Delete)
+                .header(pstr!("Content-Type"), Some(pstr!("text/plain")), pool)
+                .body(Box::new(*b"gone"))
+                .transport(transport)
+                .perform(pool);
+            kiruna::test::test_await(future, Duration::from_secs(10)).unwrap();
+        });
+        let requests = server.join().unwrap();
+        //301 keeps a PUT, then 302 keeps it too
+        assert!(requests[1].starts_with("PUT /put HTTP/1.1\r\n") && requests[1].ends_with("\r\n\r\nput"), "{}", requests[1]);
+        assert!(requests[2].starts_with("PUT /post HTTP/1.1\r\n"), "{}", requests[2]);
+        assert!(requests[3].contains("Content-Type: text/plain\r\n") && requests[3].ends_with("gone"), "{}", requests[3]);
+        //303 drops the body, and the fields that described it
+        assert!(requests[4].starts_with("GET /delete HTTP/1.1\r\n"), "{}", requests[4]);
+        assert!(!requests[4].contains("Content-") && requests[4].ends_with("\r\n\r\n"), "{}", requests[4]);
+    }
+
+    #[test] fn redirect_to_another_host() {
+        let (other, other_server) = serve(&["HTTP/1.1 200 OK\r\nConnection: close\r\n\r\n"]);
+        let redirect: &'static str = Box::leak(format!("HTTP/1.1 307 Temporary Redirect\r\nLocation: {}/landing\r\nContent-Length: 0\r\n\r\n", other).into_boxed_str());
+        let (url, server) = serve(Box::leak(vec![redirect].into_boxed_slice()));
+        autoreleasepool(|pool| {
+            let future = Request::new(url, pool).unwrap()
+                .header(pstr!("Authorization"), Some(pstr!("token secret")), pool)
+                .header(pstr!("Cookie"), Some(pstr!("session=1")), pool)
+                .header(pstr!("Host"), Some(pstr!("origin.example")), pool)
+                .header(pstr!("X-Kept"), Some(pstr!("yes")), pool)
+                .transport(Arc::new(StdTransport::new()))
+                .perform(pool);
+            kiruna::test::test_await(future, Duration::from_secs(10)).unwrap();
+        });
+        assert!(server.join().unwrap()[0].contains("Authorization: token secret\r\n"));
+        let landed = &other_server.join().unwrap()[0];
+        assert!(landed.starts_with("GET /landing HTTP/1.1\r\n"), "{}", landed);
+        assert!(landed.contains("X-Kept: yes\r\n") && landed.contains(&format!("Host: {}\r\n", other.trim_start_matches("http://"))), "{}", landed);
+        for name in ["Authorization", "Cookie", "origin.example"] {
+            assert!(!landed.contains(name), "{}", landed);
+        }
+    }
+
+    #[test] fn session_options() {
+        //the canned server plays the proxy, and its redirect is not followed
+        let (proxy, server) = serve(&["HTTP/1.1 302 Found\r\nLocation: /elsewhere\r\nContent-Length: 0\r\n\r\n"]);
+        let config = SessionConfig { follow_redirects: false, proxy: Some(proxy), ..SessionConfig::default() };
+        autoreleasepool(|pool| {
+            let transport = Arc::new(StdTransport::with_session(&config).unwrap());
+            let future = Request::new(pstr!("http://example.com/start?q=1"), pool).unwrap().transport(transport).perform(pool);
+            let response = kiruna::test::test_await(future, Duration::from_secs(10)).unwrap();
+            assert_eq!(response.status(), 302);
+            assert_eq!(response.header("location"), Some("/elsewhere"));
+        });
+        let requests = server.join().unwrap();
+        assert!(requests[0].starts_with("GET http://example.com/start?q=1 HTTP/1.1\r\nHost: example.com\r\n"), "{}", requests[0]);
+    }
+
+    #[test] fn download_to_file() {
+        let (url, server) = serve(&["HTTP/1.1 200 OK\r\nContent-Length: 13\r\nConnection: close\r\n\r\n<html></html>"]);
+        autoreleasepool(|pool| {
+            let future = Request::new(format!("{}/index.html", url), pool).unwrap().transport(Arc::new(StdTransport::new())).download(pool);
+            let response = kiruna::test::test_await(future, Duration::from_secs(10)).unwrap();
+            response.check_status().unwrap();
+            let path = response.copy_path();
+            assert_eq!(path.file_name().unwrap(), "index.html");
+            assert_eq!(std::fs::read(&path).unwrap(), b"<html></html>");
+        });
+        server.join().unwrap();
+    }
+
+    #[test] fn https_unsupported() {
+        autoreleasepool(|pool| {
+            let future = Request::new(pstr!("https://example.com"), pool).unwrap().transport(Arc::new(StdTransport::new())).perform(pool);
+            let result = kiruna::test::test_await(future, Duration::from_secs(10));
+            assert!(matches!(result, Err(crate::Error::InvalidURL(_))));
+        });
+    }
+}
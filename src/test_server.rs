@@ -0,0 +1,259 @@
+/*!
+A loopback HTTP/1.1 server with scripted routes, for exercising [crate::Request] without the internet.
+
+Enabled with the `test-server` feature.
+
+```
+use requestr::test_server::TestServer;
+let server = TestServer::start();
+let url = server.url("/status/404");
+# assert!(url.starts_with("http://127.0.0.1:"));
+//...perform requests against url; the server stops when dropped
+```
+
+# Routes
+* `/echo` responds 200 with the request body.
+* `/echo/headers` responds 200 with the request's header lines, as `Name: value\r\n`.
+* `/echo/method` responds 200 with the request method.
+* `/response-headers?{name}={value}&...` responds 200 with each query pair as a header field.  Values are
+  percent-decoded.
+* `/status/{code}` responds with that status and an empty body.
+* `/redirect/{n}` redirects `n` times, through `/redirect/{n-1}`, ending in a 200 with the body `redirected`.
+* `/slow/{ms}` waits `ms` milliseconds before responding 200 with the body `slow`.  If the client hangs up
+  first, [TestServer::abandoned] counts it.
+* `/chunked` responds 200 with a chunked body that decodes to [CHUNKED_BODY].
+* `/large/{len}` responds 200 with `len` bytes of [large_body].
+* `/drop` reads the request, then closes the connection without responding.
+
+Anything else is a 404.  Responses to `HEAD` have headers but no body.
+*/
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+///The decoded body of `/chunked`.
+pub const CHUNKED_BODY: &[u8] = b"hello, chunked world";
+
+///The body of `/large/{len}`: a repeating pattern that is unlikely to line up with any buffer size.
+pub fn large_body(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8).collect()
+}
+
+///A running server.  It listens until dropped.
+pub struct TestServer {
+    address: SocketAddr,
+    stop: Arc<AtomicBool>,
+    abandoned: Arc<AtomicUsize>,
+    thread: Option<JoinHandle<()>>,
+}
+impl TestServer {
+    ///Starts a server on a free loopback port.
+    pub fn start() -> TestServer {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Can't bind a loopback port");
+        let address = listener.local_addr().unwrap();
+        let stop = Arc::new(AtomicBool::new(false));
+        let abandoned = Arc::new(AtomicUsize::new(0));
+        let thread_stop = stop.clone();
+        let thread_abandoned = abandoned.clone();
+        let thread = std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                if thread_stop.load(Ordering::Relaxed) { break }
+                if let Ok(stream) = stream {
+                    let abandoned = thread_abandoned.clone();
+                    std::thread::spawn(move || {
+                        //a client going away mid-request is not the server's problem
+                        let _ = serve_connection(stream, &abandoned);
+                    });
+                }
+            }
+        });
+        TestServer { address, stop, abandoned, thread: Some(thread) }
+    }
+    ///The URL for `path` on this server, e.g. `url("/echo")`.
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.address, path)
+    }
+    ///How many `/slow` requests the client hung up on before the response was sent.
+    pub fn abandoned(&self) -> usize {
+        self.abandoned.load(Ordering::Relaxed)
+    }
+}
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        //wake the accept loop so it sees the flag
+        let _ = TcpStream::connect(self.address);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+struct Incoming {
+    method: String,
+    path: String,
+    query: String,
+    header_lines: String,
+    close: bool,
+    body: Vec<u8>,
+}
+
+///Reads one request, or `None` if the client closed the connection.
+fn read_request<R: BufRead>(reader: &mut R) -> std::io::Result<Option<Incoming>> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    let method = line.split(' ').next().unwrap_or("GET").to_owned();
+    let target = line.split(' ').nth(1).unwrap_or("/");
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let (path, query) = (path.to_owned(), query.to_owned());
+    let mut header_lines = String::new();
+    let mut content_length = 0;
+    let mut close = false;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line == "\r\n" || line == "\n" { break }
+        if let Some((name, value)) = line.split_once(':') {
+            let value = value.trim();
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.parse().unwrap_or(0);
+            }
+            else if name.eq_ignore_ascii_case("connection") && value.eq_ignore_ascii_case("close") {
+                close = true;
+            }
+        }
+        header_lines.push_str(line.trim_end());
+        header_lines.push_str("\r\n");
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+    Ok(Some(Incoming { method, path, query, header_lines, close, body }))
+}
+
+fn reason(code: u16) -> &'static str {
+    crate::StatusCode::new(code).and_then(crate::StatusCode::canonical_reason).unwrap_or("Unknown")
+}
+
+struct Responder<'a> {
+    stream: &'a mut TcpStream,
+    head_only: bool,
+    close: bool,
+}
+impl Responder<'_> {
+    fn head(&mut self, code: u16, headers: &str) -> std::io::Result<()> {
+        let connection = if self.close { "Connection: close\r\n" } else { "" };
+        self.stream.write_all(format!("HTTP/1.1 {} {}\r\n{}{}\r\n", code, reason(code), headers, connection).as_bytes())
+    }
+    fn body(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        if !self.head_only {
+            self.stream.write_all(bytes)?;
+        }
+        self.stream.flush()
+    }
+    fn respond(&mut self, code: u16, extra_headers: &str, body: &[u8]) -> std::io::Result<()> {
+        self.head(code, &format!("Content-Length: {}\r\n{}", body.len(), extra_headers))?;
+        self.body(body)
+    }
+}
+
+///Waits out `duration`, or returns `false` early if the client hangs up.
+fn wait_for_client(stream: &TcpStream, duration: Duration) -> std::io::Result<bool> {
+    let deadline = Instant::now() + duration;
+    stream.set_read_timeout(Some(Duration::from_millis(10)))?;
+    let mut byte = [0];
+    let connected = loop {
+        let now = Instant::now();
+        if now >= deadline { break true }
+        match stream.peek(&mut byte) {
+            Ok(0) => break false,
+            //a pipelined request; it will be read later
+            Ok(_) => std::thread::sleep((deadline - now).min(Duration::from_millis(10))),
+            Err(e) if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => {}
+            Err(_) => break false,
+        }
+    };
+    stream.set_read_timeout(None)?;
+    Ok(connected)
+}
+
+fn serve_connection(mut stream: TcpStream, abandoned: &AtomicUsize) -> std::io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    while let Some(request) = read_request(&mut reader)? {
+        let close = request.close;
+        let mut responder = Responder { stream: &mut stream, head_only: request.method == "HEAD", close };
+        let segments: Vec<&str> = request.path.trim_start_matches('/').split('/').collect();
+        let number = segments.get(1).and_then(|s| s.parse::<u64>().ok());
+        match (segments[0], segments.len(), number) {
+            ("echo", 1, _) => responder.respond(200, "", &request.body)?,
+            ("echo", 2, _) if segments[1] == "headers" => responder.respond(200, "", request.header_lines.as_bytes())?,
+            ("echo", 2, _) if segments[1] == "method" => responder.respond(200, "", request.method.as_bytes())?,
+            ("response-headers", 1, _) => {
+                let headers: String = request.query.split('&').filter_map(|pair| pair.split_once('='))
+                    .map(|(name, value)| format!("{}: {}\r\n", name, String::from_utf8_lossy(&crate::url::decode(value))))
+                    .collect();
+                responder.respond(200, &headers, b"")?
+            }
+            ("status", 2, Some(code)) if (200..1000).contains(&code) => responder.respond(code as u16, "", b"")?,
+            ("redirect", 2, Some(0)) => responder.respond(200, "", b"redirected")?,
+            ("redirect", 2, Some(n)) => responder.respond(302, &format!("Location: /redirect/{}\r\n", n - 1), b"")?,
+            ("slow", 2, Some(ms)) => {
+                if !wait_for_client(responder.stream, Duration::from_millis(ms))? {
+                    abandoned.fetch_add(1, Ordering::Relaxed);
+                    return Ok(());
+                }
+                responder.respond(200, "", b"slow")?
+            }
+            ("chunked", 1, _) => {
+                responder.head(200, "Transfer-Encoding: chunked\r\n")?;
+                for chunk in CHUNKED_BODY.chunks(6) {
+                    let mut encoded = format!("{:x}\r\n", chunk.len()).into_bytes();
+                    encoded.extend_from_slice(chunk);
+                    encoded.extend_from_slice(b"\r\n");
+                    responder.body(&encoded)?;
+                }
+                responder.body(b"0\r\n\r\n")?;
+            }
+            ("large", 2, Some(len)) => responder.respond(200, "", &large_body(len as usize))?,
+            ("drop", 1, _) => return Ok(()),
+            _ => responder.respond(404, "", b"")?,
+        }
+        if close { return Ok(()) }
+    }
+    Ok(())
+}
+
+#[cfg(test)] mod test {
+    use super::{TestServer, large_body};
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+
+    fn raw(server: &TestServer, request: &str) -> Vec<u8> {
+        let mut stream = TcpStream::connect(server.url("").trim_start_matches("http://")).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+        response
+    }
+
+    #[test] fn routes() {
+        let server = TestServer::start();
+        let echo = raw(&server, "POST /echo HTTP/1.1\r\nConnection: close\r\nContent-Length: 3\r\n\r\nabc");
+        assert!(echo.starts_with(b"HTTP/1.1 200 OK\r\nContent-Length: 3\r\n"));
+        assert!(echo.ends_with(b"\r\n\r\nabc"));
+        let headers = raw(&server, "GET /echo/headers HTTP/1.1\r\nX-Test: 1\r\nConnection: close\r\n\r\n");
+        assert!(headers.ends_with(b"\r\n\r\nX-Test: 1\r\nConnection: close\r\n"));
+        assert!(raw(&server, "GET /response-headers?ETag=x&Link=a&Link=b HTTP/1.1\r\nConnection: close\r\n\r\n").ends_with(b"\r\nETag: x\r\nLink: a\r\nLink: b\r\nConnection: close\r\n\r\n"));
+        assert!(raw(&server, "GET /status/503 HTTP/1.1\r\nConnection: close\r\n\r\n").starts_with(b"HTTP/1.1 503 Service Unavailable\r\n"));
+        assert!(raw(&server, "GET /redirect/2 HTTP/1.1\r\nConnection: close\r\n\r\n").windows(23).any(|w| w == b"Location: /redirect/1\r\n"));
+        assert!(raw(&server, "GET /chunked HTTP/1.1\r\nConnection: close\r\n\r\n").ends_with(b"\r\n6\r\ned wor\r\n2\r\nld\r\n0\r\n\r\n"));
+        assert!(raw(&server, "GET /large/1000 HTTP/1.1\r\nConnection: close\r\n\r\n").ends_with(&large_body(1000)));
+        assert!(raw(&server, "GET /drop HTTP/1.1\r\n\r\n").is_empty());
+        assert_eq!(raw(&server, "HEAD /large/10 HTTP/1.1\r\nConnection: close\r\n\r\n"), b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\nConnection: close\r\n\r\n");
+        assert!(raw(&server, "DELETE /echo/method HTTP/1.1\r\nConnection: close\r\n\r\n").ends_with(b"\r\n\r\nDELETE"));
+        assert!(raw(&server, "GET /nowhere HTTP/1.1\r\nConnection: close\r\n\r\n").starts_with(b"HTTP/1.1 404 Not Found\r\n"));
+    }
+}
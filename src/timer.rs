@@ -0,0 +1,146 @@
+/*!
+Timers that don't need a particular executor.
+
+Every pending timer shares one thread, which sleeps until the earliest deadline.
+*/
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Condvar, Mutex, OnceLock};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+use crate::{Error, PlatformError};
+
+#[derive(Default)]
+struct Timers {
+    ///Deadlines by timer, soonest first.  Entries for dropped timers stay until their deadline passes.
+    deadlines: BinaryHeap<Reverse<(Instant, u64)>>,
+    ///The wakers of pending timers.
+    wakers: HashMap<u64, Waker>,
+    next_id: u64,
+}
+
+struct Shared {
+    timers: Mutex<Timers>,
+    ///Signalled when a timer is added, in case it's now the earliest.
+    changed: Condvar,
+}
+
+///The timer thread's state, starting the thread the first time it's needed.
+fn shared() -> &'static Shared {
+    static SHARED: OnceLock<Shared> = OnceLock::new();
+    static STARTED: std::sync::Once = std::sync::Once::new();
+    let shared = SHARED.get_or_init(|| Shared { timers: Mutex::new(Timers::default()), changed: Condvar::new() });
+    STARTED.call_once(|| {
+        std::thread::Builder::new().name("requestr-timer".to_owned()).spawn(move || run(shared)).expect("Can't spawn thread");
+    });
+    shared
+}
+
+fn run(shared: &Shared) {
+    let mut timers = shared.timers.lock().unwrap();
+    loop {
+        let now = Instant::now();
+        while let Some(&Reverse((deadline, id))) = timers.deadlines.peek() {
+            if deadline > now {
+                break;
+            }
+            timers.deadlines.pop();
+            if let Some(waker) = timers.wakers.remove(&id) {
+                waker.wake();
+            }
+        }
+        timers = match timers.deadlines.peek() {
+            Some(&Reverse((deadline, _))) => shared.changed.wait_timeout(timers, deadline.saturating_duration_since(now)).unwrap().0,
+            None => shared.changed.wait(timers).unwrap(),
+        };
+    }
+}
+
+///Completes after a duration, without needing a particular executor.
+pub(crate) struct Delay {
+    deadline: Instant,
+    ///Our entry with the timer thread, once we've been polled.
+    id: Option<u64>,
+}
+impl Delay {
+    pub fn new(duration: Duration) -> Self {
+        Delay { deadline: Instant::now() + duration, id: None }
+    }
+}
+impl Future for Delay {
+    type Output = ();
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if Instant::now() >= self.deadline {
+            return Poll::Ready(());
+        }
+        let shared = shared();
+        let mut timers = shared.timers.lock().unwrap();
+        match self.id {
+            Some(id) => {
+                timers.wakers.insert(id, cx.waker().clone());
+            }
+            None => {
+                let id = timers.next_id;
+                timers.next_id += 1;
+                timers.deadlines.push(Reverse((self.deadline, id)));
+                timers.wakers.insert(id, cx.waker().clone());
+                self.id = Some(id);
+                shared.changed.notify_one();
+            }
+        }
+        Poll::Pending
+    }
+}
+impl Drop for Delay {
+    fn drop(&mut self) {
+        if let Some(id) = self.id {
+            shared().timers.lock().unwrap().wakers.remove(&id);
+        }
+    }
+}
+
+///Runs `future`, failing with [Error::Timeout] if it takes longer than `limit`.
+///
+/// On timeout, `future` is dropped, which cancels the request.
+pub(crate) async fn timeout<T, F: Future<Output=Result<T,Error>>>(limit: Option<Duration>, future: F) -> Result<T,Error> {
+    let mut future = std::pin::pin!(future);
+    let mut delay = limit.map(Delay::new);
+    std::future::poll_fn(|cx| {
+        if let Poll::Ready(result) = future.as_mut().poll(cx) {
+            return Poll::Ready(result);
+        }
+        match delay.as_mut().map(|delay| Pin::new(delay).poll(cx)) {
+            Some(Poll::Ready(())) => {
+                let limit = limit.unwrap_or_default();
+                Poll::Ready(Err(Error::Timeout(PlatformError::message(&format!("no response within {:?}", limit)))))
+            }
+            _ => Poll::Pending,
+        }
+    }).await
+}
+
+#[cfg(test)] mod test {
+    use super::{Delay, shared};
+    use std::future::Future;
+    use std::task::{Context, Waker};
+    use std::time::{Duration, Instant};
+
+    #[test] fn shared_thread() {
+        let mut delays: Vec<_> = (0..100).map(|_| Box::pin(Delay::new(Duration::from_secs(60)))).collect();
+        for delay in &mut delays {
+            assert!(delay.as_mut().poll(&mut Context::from_waker(Waker::noop())).is_pending());
+        }
+        let ids: Vec<u64> = delays.iter().map(|d| d.id.unwrap()).collect();
+        //dropping a timer forgets its waker, rather than leaving anything waiting out the full minute
+        drop(delays);
+        let timers = shared().timers.lock().unwrap();
+        assert!(ids.iter().all(|id| !timers.wakers.contains_key(id)));
+        drop(timers);
+
+        let start = Instant::now();
+        kiruna::test::test_await(Delay::new(Duration::from_millis(50)), Duration::from_secs(5));
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+}
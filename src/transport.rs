@@ -0,0 +1,170 @@
+/*!
+Pluggable transports.
+
+A [Transport] takes a fully-built [RequestDescription] and produces a [Response].  Each platform backend
+is a transport, and [Request::perform](crate::Request::perform) dispatches through [default_transport]
+unless you choose another with [Request::transport](crate::Request::transport).
+
+Implement this trait yourself to inject a transport for testing, proxying or instrumentation.  Wrapping
+the platform transport is a matter of holding a [default_transport] and forwarding to it.
+*/
+use std::future::Future;
+use std::io::Write;
+use std::pin::Pin;
+use std::sync::{Arc, OnceLock};
+#[cfg(any(target_os = "macos", target_os = "windows"))]
+use std::task::{Context, Poll};
+use crate::{Error, Response, Downloaded};
+use crate::client::SessionConfig;
+use crate::request::RequestDescription;
+
+///The future returned by a [Transport].
+///
+/// It's `Send`, so requests can be awaited on multithreaded executors.
+pub type BoxFuture<T> = Pin<Box<dyn Future<Output=T> + Send>>;
+
+///A future that holds platform objects that are thread-safe, but that the bindings don't mark `Send`.
+#[cfg(any(target_os = "macos", target_os = "windows"))]
+pub(crate) struct AssertSend<F>(F);
+#[cfg(any(target_os = "macos", target_os = "windows"))]
+impl<F> AssertSend<F> {
+    ///# Safety
+    /// Everything `future` holds must be safe to use and drop from another thread.
+    pub(crate) unsafe fn new(future: F) -> Self {
+        AssertSend(future)
+    }
+}
+#[cfg(any(target_os = "macos", target_os = "windows"))]
+unsafe impl<F> Send for AssertSend<F> {}
+#[cfg(any(target_os = "macos", target_os = "windows"))]
+impl<F: Future> Future for AssertSend<F> {
+    type Output = F::Output;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        //structural pinning: the future is never moved out
+        unsafe { self.map_unchecked_mut(|s| &mut s.0) }.poll(cx)
+    }
+}
+
+///Something that can carry out a request.
+pub trait Transport: Send + Sync {
+    ///Performs the request, reading the response body into memory.
+    ///
+    /// Dropping the future should cancel the request.
+    fn perform(&self, request: RequestDescription) -> BoxFuture<Result<Response,Error>>;
+
+    ///Downloads the request into a file named [RequestDescription::file_name] in a temporary directory.
+    ///
+    /// The default implementation calls [Transport::perform] and writes the body out afterwards.
+    fn download(&self, request: RequestDescription) -> BoxFuture<Result<Downloaded,Error>> {
+        download_with_perform(self, request)
+    }
+}
+
+///Downloads by performing the request and writing the body out afterwards.
+pub(crate) fn download_with_perform<T: Transport + ?Sized>(transport: &T, request: RequestDescription) -> BoxFuture<Result<Downloaded,Error>> {
+    let file_name = request.file_name().to_owned();
+    let response = transport.perform(request);
+    Box::pin(async move {
+        let response = response.await?;
+        let dir = tempfile::tempdir().map_err(Error::Io)?;
+        let path = dir.path().join(file_name);
+        let mut file = std::fs::File::create(&path).map_err(Error::Io)?;
+        file.write_all(response.data().as_slice()).map_err(Error::Io)?;
+        Ok(Downloaded::new(dir, path, response.metadata().clone()))
+    })
+}
+
+#[cfg(target_os = "macos")]
+pub use crate::macos::URLSessionTransport;
+
+#[cfg(target_os = "windows")]
+pub use crate::windows::HttpClientTransport;
+
+#[cfg(target_os = "linux")]
+pub use crate::linux::CurlTransport;
+
+#[cfg(feature = "std-backend")]
+pub use crate::std_backend::StdTransport;
+
+#[cfg(not(any(target_os = "macos", target_os = "windows", target_os = "linux", feature = "std-backend")))]
+compile_error!("requestr: unsupported target; enable the std-backend feature");
+
+///The transport for this platform's HTTP stack.
+///
+/// This is what requests use unless told otherwise.  Every call returns the same transport, so requests that use it
+/// share one connection pool.  For a pool with its own settings, see [crate::Client].
+pub fn default_transport() -> Arc<dyn Transport> {
+    static DEFAULT: OnceLock<Arc<dyn Transport>> = OnceLock::new();
+    DEFAULT.get_or_init(|| {
+        #[cfg(target_os = "macos")]
+        return Arc::new(URLSessionTransport::new());
+        #[cfg(target_os = "windows")]
+        return Arc::new(HttpClientTransport::new());
+        #[cfg(target_os = "linux")]
+        return Arc::new(CurlTransport::new());
+        #[cfg(all(feature = "std-backend", not(any(target_os = "macos", target_os = "windows", target_os = "linux"))))]
+        return Arc::new(StdTransport::new());
+    }).clone()
+}
+
+///A transport for this platform's HTTP stack with its own connection pool.
+pub(crate) fn session_transport(config: &SessionConfig) -> Result<Arc<dyn Transport>,Error> {
+    #[cfg(target_os = "macos")]
+    return Ok(Arc::new(URLSessionTransport::with_session(config)?));
+    #[cfg(target_os = "windows")]
+    return Ok(Arc::new(HttpClientTransport::with_session(config)?));
+    #[cfg(target_os = "linux")]
+    return Ok(Arc::new(CurlTransport::with_session(config)?));
+    #[cfg(all(feature = "std-backend", not(any(target_os = "macos", target_os = "windows", target_os = "linux"))))]
+    return Ok(Arc::new(StdTransport::with_session(config)?));
+}
+
+#[cfg(test)] mod test {
+    use super::{Transport, BoxFuture};
+    use crate::{Request, Response, Error, Method, StatusCode};
+    use crate::request::RequestDescription;
+    use pcore::pstr;
+    use pcore::release_pool::autoreleasepool;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    ///Answers every request with its own URL, remembering what it saw.
+    #[derive(Default)]
+    struct Echo(Mutex<Vec<RequestDescription>>);
+    impl Transport for Echo {
+        fn perform(&self, request: RequestDescription) -> BoxFuture<Result<Response,Error>> {
+            let body = request.url().as_str().as_bytes().to_vec();
+            self.0.lock().unwrap().push(request);
+            Box::pin(async move { Ok(Response::new(StatusCode::OK, body.into())) })
+        }
+    }
+
+    #[test] fn inject() {
+        let echo = Arc::new(Echo::default());
+        autoreleasepool(|pool| {
+            let future = Request::new(pstr!("https://example.com/a"), pool).unwrap()
+                .method(Method::Put)
+                .header(pstr!("Accept"), Some(pstr!("text/plain")), pool)
+                .transport(echo.clone())
+                .perform(pool);
+            let response = kiruna::test::test_await(future, Duration::from_secs(1)).unwrap();
+            assert_eq!(response.check_status().unwrap().as_slice(), b"https://example.com/a");
+        });
+        let seen = echo.0.lock().unwrap();
+        assert_eq!(seen[0].method(), "PUT");
+        assert_eq!(seen[0].headers().iter().collect::<Vec<_>>(), [("User-Agent", crate::DEFAULT_USER_AGENT), ("Accept", "text/plain")]);
+    }
+
+    #[test] fn default_download() {
+        autoreleasepool(|pool| {
+            let future = Request::new(pstr!("https://example.com/b.txt"), pool).unwrap()
+                .transport(Arc::new(Echo::default()))
+                .download(pool);
+            let downloaded = kiruna::test::test_await(future, Duration::from_secs(1)).unwrap();
+            downloaded.check_status().unwrap();
+            let path = downloaded.copy_path();
+            assert_eq!(path.file_name().unwrap(), "b.txt");
+            assert_eq!(std::fs::read(&path).unwrap(), b"https://example.com/b.txt");
+        });
+    }
+}
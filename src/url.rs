@@ -0,0 +1,572 @@
+/*!
+URLs, parsed and checked the same way on every platform.
+
+[Request::new](crate::Request::new) parses its URL into a [Url] before any backend sees it, so a URL that one
+platform would reject fails on all of them, immediately, with [Error::InvalidURL].
+
+```
+use requestr::Url;
+let url = Url::parse("HTTPS://Bücher.example:443/a/./b/../c d?q=ü#top").unwrap();
+assert_eq!(url.as_str(), "https://xn--bcher-kva.example/a/c%20d?q=%C3%BC#top");
+assert_eq!(url.host(), "xn--bcher-kva.example");
+assert_eq!(url.port_or_known_default(), Some(443));
+assert_eq!(url.join("../up?x").unwrap().as_str(), "https://xn--bcher-kva.example/up?x");
+```
+*/
+use std::fmt::{Debug, Display, Formatter};
+use std::str::FromStr;
+use pcore::string::IntoParameterString;
+use pcore::release_pool::ReleasePool;
+use crate::Error;
+
+///An absolute URL with an authority, such as `https://example.com/path?query`.
+///
+/// Parsing normalizes the URL: the scheme and host are lowercased, an internationalized host is converted to
+/// Punycode, a default port is dropped, `.` and `..` path segments are resolved, percent-escapes are uppercased,
+/// and characters that can't appear literally (spaces, non-ASCII, and so on) are percent-encoded.
+#[derive(Clone,PartialEq,Eq,Hash)]
+pub struct Url {
+    serialization: String,
+    scheme: String,
+    userinfo: Option<String>,
+    host: String,
+    port: Option<u16>,
+    path: String,
+    query: Option<String>,
+    fragment: Option<String>,
+}
+
+///Something [crate::Request::new] accepts as a URL: a [Url], or a string to parse as one.
+pub trait IntoUrl {
+    fn into_url(self, pool: &ReleasePool) -> Result<Url,Error>;
+}
+impl IntoUrl for Url {
+    fn into_url(self, _pool: &ReleasePool) -> Result<Url,Error> {
+        Ok(self)
+    }
+}
+impl IntoUrl for &Url {
+    fn into_url(self, _pool: &ReleasePool) -> Result<Url,Error> {
+        Ok(self.clone())
+    }
+}
+impl<'a, S: IntoParameterString<'a>> IntoUrl for S {
+    fn into_url(self, pool: &ReleasePool) -> Result<Url,Error> {
+        Url::parse(&self.into_parameter_string(pool).to_string())
+    }
+}
+
+fn default_port(scheme: &str) -> Option<u16> {
+    match scheme {
+        "http" | "ws" => Some(80),
+        "https" | "wss" => Some(443),
+        "ftp" => Some(21),
+        _ => None,
+    }
+}
+
+///Whether `s` is an RFC 3986 scheme.
+fn is_scheme(s: &str) -> bool {
+    s.starts_with(|c: char| c.is_ascii_alphabetic()) &&
+        s.bytes().all(|b| b.is_ascii_alphanumeric() || matches!(b, b'+' | b'-' | b'.'))
+}
+
+//characters that must be percent-encoded in each component, besides controls, spaces and non-ASCII
+const PATH: &[u8] = b"\"#<>?`{}";
+const QUERY: &[u8] = b"\"#<>";
+const FRAGMENT: &[u8] = b"\"<>`";
+const USERINFO: &[u8] = b"\"#<>?`{}/:;=@[\\]^|";
+
+///The byte an escape such as `%2F` at the start of `bytes` stands for.
+fn escape(bytes: &[u8]) -> Option<u8> {
+    match bytes {
+        [b'%', high, low, ..] if high.is_ascii_hexdigit() && low.is_ascii_hexdigit() => {
+            u8::from_str_radix(std::str::from_utf8(&bytes[1..3]).unwrap(), 16).ok()
+        }
+        _ => None,
+    }
+}
+
+///Percent-encodes what `reserved` and RFC 3986 don't allow literally, and uppercases existing escapes.
+///
+/// A `%` that doesn't start an escape is encoded as `%25`.
+fn encode(input: &str, reserved: &[u8]) -> String {
+    let bytes = input.as_bytes();
+    let mut output = String::with_capacity(input.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let b = bytes[index];
+        if let Some(escaped) = escape(&bytes[index..]) {
+            output.push_str(&format!("%{:02X}", escaped));
+            index += 3;
+            continue;
+        }
+        if (0x21..=0x7e).contains(&b) && b != b'%' && !reserved.contains(&b) {
+            output.push(b as char);
+        }
+        else {
+            output.push_str(&format!("%{:02X}", b));
+        }
+        index += 1;
+    }
+    output
+}
+
+///Encodes a name or value as `application/x-www-form-urlencoded` does: spaces become `+`, and everything but
+///ASCII letters, digits and `*-._` is percent-encoded.
+pub(crate) fn form_encode(input: &str) -> String {
+    let mut output = String::with_capacity(input.len());
+    for b in input.bytes() {
+        match b {
+            b' ' => output.push('+'),
+            b if b.is_ascii_alphanumeric() || matches!(b, b'*' | b'-' | b'.' | b'_') => output.push(b as char),
+            b => output.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    output
+}
+
+///Decodes percent-escapes, leaving invalid ones as they are.
+pub(crate) fn decode(input: &str) -> Vec<u8> {
+    let bytes = input.as_bytes();
+    let mut output = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        match escape(&bytes[index..]) {
+            Some(decoded) => {
+                output.push(decoded);
+                index += 3;
+            }
+            None => {
+                output.push(bytes[index]);
+                index += 1;
+            }
+        }
+    }
+    output
+}
+
+///Resolves `.` and `..` segments, as RFC 3986 section 5.2.4 describes.
+fn remove_dot_segments(path: &str) -> String {
+    let mut output: Vec<&str> = Vec::new();
+    let segments: Vec<&str> = path.split('/').skip(1).collect();
+    for (index, segment) in segments.iter().enumerate() {
+        let last = index == segments.len() - 1;
+        match *segment {
+            "." | "%2E" | "%2e" => if last { output.push("") },
+            ".." | ".%2E" | ".%2e" | "%2E." | "%2e." | "%2E%2E" | "%2e%2e" => {
+                output.pop();
+                if last {
+                    output.push("");
+                }
+            }
+            segment => output.push(segment),
+        }
+    }
+    format!("/{}", output.join("/"))
+}
+
+///Encodes one label of an internationalized host name with Punycode, as RFC 3492 describes.
+fn punycode(label: &str) -> Option<String> {
+    const BASE: u32 = 36;
+    const TMIN: u32 = 1;
+    const TMAX: u32 = 26;
+    fn adapt(delta: u32, points: u32, first: bool) -> u32 {
+        let mut delta = delta / if first { 700 } else { 2 };
+        delta += delta / points;
+        let mut k = 0;
+        while delta > ((BASE - TMIN) * TMAX) / 2 {
+            delta /= BASE - TMIN;
+            k += BASE;
+        }
+        k + (BASE - TMIN + 1) * delta / (delta + 38)
+    }
+    fn digit(d: u32) -> char {
+        (if d < 26 { b'a' + d as u8 } else { b'0' + (d - 26) as u8 }) as char
+    }
+    let code_points: Vec<u32> = label.chars().map(|c| c as u32).collect();
+    let mut output: String = label.chars().filter(char::is_ascii).collect();
+    let basic = output.len() as u32;
+    if basic > 0 {
+        output.push('-');
+    }
+    let mut handled = basic;
+    let (mut n, mut delta, mut bias) = (128u32, 0u32, 72u32);
+    while (handled as usize) < code_points.len() {
+        let m = code_points.iter().copied().filter(|&c| c >= n).min()?;
+        delta = delta.checked_add((m - n).checked_mul(handled + 1)?)?;
+        n = m;
+        for &c in &code_points {
+            if c < n {
+                delta = delta.checked_add(1)?;
+            }
+            if c == n {
+                let mut q = delta;
+                let mut k = BASE;
+                loop {
+                    let t = if k <= bias { TMIN } else if k >= bias + TMAX { TMAX } else { k - bias };
+                    if q < t {
+                        break;
+                    }
+                    output.push(digit(t + (q - t) % (BASE - t)));
+                    q = (q - t) / (BASE - t);
+                    k += BASE;
+                }
+                output.push(digit(q));
+                bias = adapt(delta, handled + 1, handled == basic);
+                delta = 0;
+                handled += 1;
+            }
+        }
+        delta += 1;
+        n += 1;
+    }
+    Some(output)
+}
+
+///Checks and normalizes a host: an IPv6 literal in brackets, or a name or IPv4 address.
+///
+/// Names are lowercased, and labels outside ASCII are converted to Punycode.  Full UTS #46 mapping isn't applied, so
+/// a name should already be in normalization form C.
+fn parse_host(host: &str) -> Option<String> {
+    if let Some(literal) = host.strip_prefix('[') {
+        let address: std::net::Ipv6Addr = literal.strip_suffix(']')?.parse().ok()?;
+        return Some(format!("[{}]", address));
+    }
+    let host = String::from_utf8(decode(host)).ok()?.to_lowercase();
+    if host.is_empty() {
+        return None;
+    }
+    let last = host.split('.').count() - 1;
+    let labels = host.split('.').enumerate().map(|(index, label)| {
+        match label {
+            //a trailing dot names the root
+            "" if index > 0 && index == last => Some(String::new()),
+            "" => None,
+            label if label.is_ascii() => Some(label.to_owned()),
+            label => punycode(label).map(|encoded| format!("xn--{}", encoded)),
+        }
+    }).collect::<Option<Vec<_>>>()?;
+    let host = labels.join(".");
+    host.bytes().all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.')).then_some(host)
+}
+
+impl Url {
+    ///Parses an absolute URL, such as `https://example.com/`.
+    ///
+    /// # Errors
+    /// [Error::InvalidURL] if `url` has no scheme or authority, has an invalid host or port, or contains control
+    /// characters.
+    pub fn parse(url: &str) -> Result<Url,Error> {
+        let invalid = || Error::InvalidURL(url.to_owned());
+        let trimmed = url.trim_matches(|c: char| c.is_ascii_whitespace());
+        if trimmed.chars().any(|c| c.is_control()) {
+            return Err(invalid());
+        }
+        let (scheme, rest) = trimmed.split_once("://").ok_or_else(invalid)?;
+        if !is_scheme(scheme) {
+            return Err(invalid());
+        }
+        let scheme = scheme.to_ascii_lowercase();
+        let (rest, fragment) = match rest.split_once('#') {
+            Some((rest, fragment)) => (rest, Some(encode(fragment, FRAGMENT))),
+            None => (rest, None),
+        };
+        let (rest, query) = match rest.split_once('?') {
+            Some((rest, query)) => (rest, Some(encode(query, QUERY))),
+            None => (rest, None),
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(index) => (&rest[..index], &rest[index..]),
+            None => (rest, "/"),
+        };
+        let (userinfo, host_port) = match authority.rsplit_once('@') {
+            Some((userinfo, host_port)) => {
+                let userinfo = match userinfo.split_once(':') {
+                    Some((username, password)) => format!("{}:{}", encode(username, USERINFO), encode(password, USERINFO)),
+                    None => encode(userinfo, USERINFO),
+                };
+                (Some(userinfo), host_port)
+            }
+            None => (None, authority),
+        };
+        //IPv6 literals contain colons, but end in `]` when there's no port
+        let (host, port) = match host_port.rsplit_once(':') {
+            Some((host, port)) if !port.contains(']') => (host, port),
+            _ => (host_port, ""),
+        };
+        let host = parse_host(host).ok_or_else(invalid)?;
+        let port = match port {
+            "" => None,
+            port if port.bytes().all(|b| b.is_ascii_digit()) => Some(port.parse::<u16>().map_err(|_| invalid())?),
+            _ => return Err(invalid()),
+        }.filter(|&port| Some(port) != default_port(&scheme));
+        let path = remove_dot_segments(&encode(path, PATH));
+        Ok(Url::from_parts(scheme, userinfo, host, port, path, query, fragment))
+    }
+    fn from_parts(scheme: String, userinfo: Option<String>, host: String, port: Option<u16>, path: String, query: Option<String>, fragment: Option<String>) -> Url {
+        let mut url = Url { serialization: String::new(), scheme, userinfo, host, port, path, query, fragment };
+        url.serialize();
+        url
+    }
+    fn serialize(&mut self) {
+        self.serialization = format!("{}://{}{}", self.scheme, self.authority(), self.request_target());
+        if let Some(fragment) = &self.fragment {
+            self.serialization.push('#');
+            self.serialization.push_str(fragment);
+        }
+    }
+    ///Appends `pairs`, such as `a=1&b=2`, to the query.  They must already be encoded.
+    pub(crate) fn append_query(&mut self, pairs: &str) {
+        if pairs.is_empty() {
+            return;
+        }
+        self.query = Some(match self.query.take() {
+            Some(query) if !query.is_empty() => format!("{}&{}", query, pairs),
+            _ => pairs.to_owned(),
+        });
+        self.serialize();
+    }
+    ///Resolves `reference` against this URL, as a browser resolves a link on this page.
+    ///
+    /// `reference` may be absolute (`https://other.example/`), scheme-relative (`//other.example/`), or relative
+    /// (`/root`, `sibling`, `../up`, `?query`, `#fragment`).
+    ///
+    /// # Errors
+    /// [Error::InvalidURL] if the result isn't a valid URL.
+    pub fn join(&self, reference: &str) -> Result<Url,Error> {
+        let reference = reference.trim_matches(|c: char| c.is_ascii_whitespace());
+        if reference.split_once(':').is_some_and(|(scheme, _)| is_scheme(scheme)) {
+            return Url::parse(reference);
+        }
+        if reference.starts_with("//") {
+            return Url::parse(&format!("{}:{}", self.scheme, reference));
+        }
+        let (rest, fragment) = match reference.split_once('#') {
+            Some((rest, fragment)) => (rest, Some(fragment)),
+            None => (reference, None),
+        };
+        let (path, query) = match rest.split_once('?') {
+            Some((path, query)) => (path, Some(query)),
+            None => (rest, None),
+        };
+        let (path, query) = if path.is_empty() {
+            (self.path.clone(), query.or(self.query.as_deref()))
+        }
+        else if path.starts_with('/') {
+            (path.to_owned(), query)
+        }
+        else {
+            let directory = &self.path[..self.path.rfind('/').map_or(0, |i| i + 1)];
+            (format!("{}{}", directory, path), query)
+        };
+        let mut url = format!("{}://{}{}", self.scheme, self.authority(), path);
+        if let Some(query) = query {
+            url.push('?');
+            url.push_str(query);
+        }
+        if let Some(fragment) = fragment {
+            url.push('#');
+            url.push_str(fragment);
+        }
+        Url::parse(&url)
+    }
+    pub fn as_str(&self) -> &str {
+        &self.serialization
+    }
+    ///The scheme, lowercased, such as `https`.
+    pub fn scheme(&self) -> &str {
+        &self.scheme
+    }
+    ///The user name before the host, percent-encoded, or `""`.
+    pub fn username(&self) -> &str {
+        self.userinfo.as_deref().map_or("", |u| u.split(':').next().unwrap())
+    }
+    ///The password before the host, percent-encoded.
+    pub fn password(&self) -> Option<&str> {
+        self.userinfo.as_deref().and_then(|u| u.split_once(':')).map(|(_, password)| password)
+    }
+    ///The host, lowercased and in Punycode.  An IPv6 address keeps its brackets, as in `[::1]`.
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+    ///The port, unless it's absent or the default for the scheme.
+    pub fn port(&self) -> Option<u16> {
+        self.port
+    }
+    ///The port, or the default for the scheme if it's one we know.
+    pub fn port_or_known_default(&self) -> Option<u16> {
+        self.port.or_else(|| default_port(&self.scheme))
+    }
+    ///The path, which always starts with `/`.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+    ///The query after the `?`, if there is one.
+    pub fn query(&self) -> Option<&str> {
+        self.query.as_deref()
+    }
+    ///The fragment after the `#`, if there is one.  It's never sent to the server.
+    pub fn fragment(&self) -> Option<&str> {
+        self.fragment.as_deref()
+    }
+    ///The user information, host and port, as in `user@example.com:8080`.
+    fn authority(&self) -> String {
+        let mut authority = String::new();
+        if let Some(userinfo) = &self.userinfo {
+            authority.push_str(userinfo);
+            authority.push('@');
+        }
+        authority.push_str(&self.host);
+        if let Some(port) = self.port {
+            authority.push_str(&format!(":{}", port));
+        }
+        authority
+    }
+    ///The path and query, as sent in an HTTP/1.1 request line.
+    pub(crate) fn request_target(&self) -> String {
+        match &self.query {
+            Some(query) => format!("{}?{}", self.path, query),
+            None => self.path.clone(),
+        }
+    }
+    ///A file name from the last path segment, decoded, or `None` if there isn't one.
+    ///
+    /// Separators, control characters and the other characters Windows doesn't allow in names are replaced with `_`,
+    /// so the name can't escape its directory.  A name Windows reserves for a device, such as `CON` or `com1.txt`, gets
+    /// a `_` in front.
+    pub(crate) fn file_name(&self) -> Option<String> {
+        let segment = self.path.rsplit('/').next().unwrap();
+        let name: String = String::from_utf8_lossy(&decode(segment)).chars()
+            .map(|c| if matches!(c, '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|') || c.is_control() { '_' } else { c })
+            .collect();
+        if matches!(name.as_str(), "" | "." | "..") {
+            return None;
+        }
+        //Windows ignores the extension and trailing spaces when it looks for device names
+        let stem = name.split('.').next().unwrap().trim_end_matches(' ').to_ascii_uppercase();
+        let numbered = stem.strip_prefix("COM").or_else(|| stem.strip_prefix("LPT")).is_some_and(|n| {
+            let mut chars = n.chars();
+            matches!((chars.next(), chars.next()), (Some('1'..='9' | '\u{b9}' | '\u{b2}' | '\u{b3}'), None))
+        });
+        if numbered || matches!(stem.as_str(), "CON" | "PRN" | "AUX" | "NUL") {
+            return Some(format!("_{}", name));
+        }
+        Some(name)
+    }
+}
+impl Display for Url {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.serialization)
+    }
+}
+impl Debug for Url {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(&self.serialization, f)
+    }
+}
+impl FromStr for Url {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Url::parse(s)
+    }
+}
+impl AsRef<str> for Url {
+    fn as_ref(&self) -> &str {
+        &self.serialization
+    }
+}
+impl PartialEq<str> for Url {
+    fn eq(&self, other: &str) -> bool {
+        self.serialization == other
+    }
+}
+impl PartialEq<&str> for Url {
+    fn eq(&self, other: &&str) -> bool {
+        self.serialization == *other
+    }
+}
+
+#[cfg(test)] mod test {
+    use super::{Url, punycode, form_encode};
+    use crate::Error;
+
+    #[test] fn parse() {
+        let url = Url::parse("http://user:pw@Example.COM:8080/a/b?c=d#e").unwrap();
+        assert_eq!(url.scheme(), "http");
+        assert_eq!((url.username(), url.password()), ("user", Some("pw")));
+        assert_eq!(url.host(), "example.com");
+        assert_eq!(url.port(), Some(8080));
+        assert_eq!(url.path(), "/a/b");
+        assert_eq!(url.query(), Some("c=d"));
+        assert_eq!(url.fragment(), Some("e"));
+        assert_eq!(url.as_str(), "http://user:pw@example.com:8080/a/b?c=d#e");
+
+        let url = Url::parse("https://example.com").unwrap();
+        assert_eq!((url.path(), url.query(), url.port(), url.port_or_known_default()), ("/", None, None, Some(443)));
+        assert_eq!(Url::parse("http://example.com:80?q").unwrap(), "http://example.com/?q");
+        assert_eq!(Url::parse("http://[::1]:8080/").unwrap().host(), "[::1]");
+        assert_eq!(Url::parse("http://[0:0::1]/").unwrap(), "http://[::1]/");
+        assert_eq!(Url::parse(" http://127.0.0.1:1/\n").unwrap(), "http://127.0.0.1:1/");
+    }
+
+    #[test] fn invalid() {
+        for url in ["example.com", "/relative", "http:/x", "1http://x/", "http:///path", "http://exa mple.com/",
+                    "http://example.com:99999/", "http://example.com:8o/", "http://[::g]/", "http://a/b\r\nHost: c",
+                    "http://a..b/", "http://a<b/"] {
+            assert!(matches!(Url::parse(url), Err(Error::InvalidURL(u)) if u == url), "{:?}", url);
+        }
+    }
+
+    #[test] fn normalization() {
+        assert_eq!(Url::parse("http://a/x y/%7e%zz?a b=ü#f g").unwrap(), "http://a/x%20y/%7E%25zz?a%20b=%C3%BC#f%20g");
+        assert_eq!(Url::parse("http://a/b/./c/../../d/.").unwrap().path(), "/d/");
+        assert_eq!(Url::parse("http://a/../../x").unwrap().path(), "/x");
+    }
+
+    #[test] fn idna() {
+        assert_eq!(punycode("münchen").unwrap(), "mnchen-3ya");
+        assert_eq!(Url::parse("http://MÜNCHEN.de/").unwrap().host(), "xn--mnchen-3ya.de");
+        assert_eq!(Url::parse("http://日本語.jp/").unwrap().host(), "xn--wgv71a119e.jp");
+        assert_eq!(Url::parse("http://%C3%BC.example/").unwrap().host(), "xn--tda.example");
+    }
+
+    #[test] fn join() {
+        //from RFC 3986 section 5.4
+        let base = Url::parse("http://a/b/c/d;p?q").unwrap();
+        for (reference, expected) in [("g", "http://a/b/c/g"), ("./g", "http://a/b/c/g"), ("g/", "http://a/b/c/g/"),
+                                      ("/g", "http://a/g"), ("//g", "http://g/"), ("?y", "http://a/b/c/d;p?y"),
+                                      ("g?y", "http://a/b/c/g?y"), ("#s", "http://a/b/c/d;p?q#s"), ("", "http://a/b/c/d;p?q"),
+                                      (".", "http://a/b/c/"), ("..", "http://a/b/"), ("../..", "http://a/"),
+                                      ("../../../g", "http://a/g"), ("g;x=1/../y", "http://a/b/c/y"),
+                                      ("https://other:444/x", "https://other:444/x")] {
+            assert_eq!(base.join(reference).unwrap(), expected, "{:?}", reference);
+        }
+    }
+
+    #[test] fn file_name() {
+        assert_eq!(Url::parse("http://a/b/report%20final.pdf?x").unwrap().file_name().as_deref(), Some("report final.pdf"));
+        assert_eq!(Url::parse("http://a/b/..%2F..%2Fetc").unwrap().file_name().as_deref(), Some(".._.._etc"));
+        assert_eq!(Url::parse("http://a/b/").unwrap().file_name(), None);
+        assert_eq!(Url::parse("http://a/b/a*b%3F%22%3C%3E%7C.txt").unwrap().file_name().as_deref(), Some("a_b_____.txt"));
+        for (path, expected) in [("con", "_con"), ("CON.txt", "_CON.txt"), ("Com1.tar.gz", "_Com1.tar.gz"), ("lpt9", "_lpt9"),
+                                 ("nul%20.txt", "_nul .txt"), ("aux", "_aux"), ("PRN.", "_PRN."), ("COM%C2%B9", "_COM\u{b9}"),
+                                 ("COM10.txt", "COM10.txt"), ("LPT0", "LPT0"), ("CONSOLE", "CONSOLE"), ("icon.png", "icon.png")] {
+            let url = Url::parse(&format!("http://a/{}", path)).unwrap();
+            assert_eq!(url.file_name().as_deref(), Some(expected), "{}", path);
+        }
+    }
+
+    #[test] fn append_query() {
+        assert_eq!(form_encode("a b&c=d/é*"), "a+b%26c%3Dd%2F%C3%A9*");
+        let mut url = Url::parse("http://a/b#f").unwrap();
+        url.append_query("x=1");
+        assert_eq!(url.as_str(), "http://a/b?x=1#f");
+        url.append_query("x=2");
+        url.append_query("");
+        assert_eq!(url.as_str(), "http://a/b?x=1&x=2#f");
+        let mut url = Url::parse("http://a/b?").unwrap();
+        url.append_query("y");
+        assert_eq!(url.query(), Some("y"));
+    }
+}
@@ -1,4 +1,5 @@
-pub mod request;
-mod response;
+mod request;
+pub(crate) mod response;
 pub mod strings;
 pub mod bufferbridge;
+pub use request::HttpClientTransport;
@@ -1,208 +1,309 @@
-use crate::{Error};
+use crate::{Error, Response, Downloaded, Method, StatusCode, Body};
+use crate::request::RequestDescription;
+use crate::transport::{Transport, BoxFuture, AssertSend};
+use crate::header::HeaderMap;
+use crate::response::{Metadata, Version};
+use crate::client::SessionConfig;
 use std::future::Future;
-use crate::windows::response::{Response, Downloaded};
-use std::collections::{HashMap};
-use std::mem::MaybeUninit;
-
-use pcore::string::{IntoParameterString, ParameterString, U16ZErasedLength};
-use pcore::release_pool::ReleasePool;
-use pcore::pstr;
-use windows::Foundation::IAsyncOperationWithProgress;
-use windows::Web::Http::{HttpResponseMessage,HttpProgress};
+use crate::windows::response::Buffer;
+
+use windows::core::{HSTRING, Interface};
+use windows::Foundation::{IAsyncInfo, IAsyncOperationWithProgress};
+use windows::Web::Http::{HttpClient,HttpResponseMessage,HttpProgress,IHttpContent};
 use crate::windows::bufferbridge::WinBuffer;
 use winfuture::AsyncFuture;
+use tempfile::{tempdir, NamedTempFile, TempPath};
 
-pub struct Request<'a> {
-    url: ParameterString<'a>,
-    headers: HashMap<ParameterString<'a>,ParameterString<'a>>,
-    body: Option<WinBuffer>,
-    method: ParameterString<'a>,
-}
-
-
-
-
-impl<'a> Request<'a> {
-    ///Create a new builder with the given URL.
-    ///
-    /// # Errors
-    /// On Windows, this will not fail.
-    // - todo: We could potentially optimize this by writing our options into a rust-like struct
-    // and eliding a bunch of intermediate autoreleasepools into one big fn
-    pub fn new<U: IntoParameterString<'a>>(url: U, pool: &ReleasePool) ->
-    Result<Request<'a>, Error> {
-        Ok(
-            Request{
-                url: url.into_parameter_string(pool),
-                headers: HashMap::new(),
-                method: pstr!("GET").into_parameter_string(pool),
-                body: None
-            }
-        )
-
+///Reads the status line and final URL, and collects the message headers and the content headers, which WinRT
+/// keeps separately.
+fn metadata(response: &HttpResponseMessage) -> Result<Metadata,Error> {
+    use windows::Web::Http::HttpVersion;
+    let mut headers = HeaderMap::new();
+    for pair in response.Headers()?.First()? {
+        headers.append(&pair.Key()?.to_string(), &pair.Value()?.to_string());
     }
-    ///Set (or unset) a header field
-    pub fn header<K: IntoParameterString<'a>,V:IntoParameterString<'a>>(mut self, key: K,value: Option<V>, pool: &ReleasePool) -> Self {
-        match value {
-            Some(v) => {self.headers.insert(key.into_parameter_string(pool), v.into_parameter_string(pool));}
-            None => {
-                self.headers.remove(&key.into_parameter_string(pool));
-            }
-        }
-        self
+    for pair in response.Content()?.Headers()?.First()? {
+        headers.append(&pair.Key()?.to_string(), &pair.Value()?.to_string());
     }
-    ///Set the HTTP method.
-    pub fn method<P: IntoParameterString<'a>>(mut self, method: P, pool: &ReleasePool) -> Self{
-        self.method = method.into_parameter_string(pool);
-        self
+    let version = match response.Version()? {
+        HttpVersion::Http10 => Some(Version::Http10),
+        HttpVersion::Http11 => Some(Version::Http11),
+        HttpVersion::Http20 => Some(Version::Http2),
+        _ => None,
+    };
+    let reason = response.ReasonPhrase()?.to_string();
+    Ok(Metadata {
+        code: StatusCode::from_platform(response.StatusCode()?.0 as u16),
+        reason: if reason.is_empty() { None } else { Some(reason) },
+        version,
+        //HttpClient updates the request message as it follows redirects
+        url: response.RequestMessage()?.RequestUri()?.AbsoluteUri()?.to_string(),
+        headers,
+        ..Metadata::default()
+    })
+}
+
+///Cancels a WinRT operation when dropped.
+///
+/// `AsyncFuture` only waits for the operation, so without this, dropping our future would leave the request running.
+/// Cancelling an operation that has already completed does nothing.
+struct CancelOnDrop(IAsyncInfo);
+impl CancelOnDrop {
+    fn new<I: Interface>(operation: &I) -> Result<Self,Error> {
+        Ok(CancelOnDrop(operation.cast()?))
     }
-    ///Set the HTTP body data.
-    pub fn body(mut self, body: Box<[u8]>) -> Self {
-        self.body = Some(WinBuffer(body));
-        self
+}
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        let _ = self.0.Cancel();
     }
+}
 
-    pub fn perform(self, _release_pool: &ReleasePool) -> impl Future<Output=Result<Response,Error>> + 'a {
-            let deferred_request = DeferredRequest::new(self);
-            async {
-                let new_request = AsyncFuture::new(deferred_request.perform().unwrap());
-                let r = new_request.await?;
-                Ok(Response::new(r))
-            }
-        }
+fn perform(client: HttpClient, request: RequestDescription) -> impl Future<Output=Result<Response,Error>> {
+    let deferred_request = DeferredRequest::new(request);
+    async move {
+        let (operation, _spool) = deferred_request.perform(&client).await?;
+        let _cancel = CancelOnDrop::new(&operation)?;
+        let response = AsyncFuture::new(operation).await?;
+        let metadata = metadata(&response)?;
+        //read the body now, so Response::check_status and Data work the same as on other platforms
+        let content = response.Content()?;
+        let operation = content.ReadAsBufferAsync()?;
+        let _cancel = CancelOnDrop::new(&operation)?;
+        let buffer = AsyncFuture::new(operation).await?;
+        let byte_access = buffer.cast()?;
+        Ok(Response::from_metadata(metadata, Buffer(byte_access).into()))
+    }
+}
 
-    ///Downloads the request into a file.
-    ///
-    /// The file will be located in a temporary directory and will be deleted when the return value is dropped.
-    pub fn download(self, _pool: &ReleasePool) -> impl Future<Output=Result<Downloaded,Error>> + 'a{
-        async {
-            use windows::Storage::{StorageFile,FileAccessMode};
-            use windows::Win32::Storage::FileSystem::{GetTempPathW,GetTempFileNameW};
-            use windows::Win32::Foundation::{MAX_PATH,PWSTR};
-            use windows::Storage::Streams::IOutputStream;
-            let deferred_request = DeferredRequest::new(self);
-            let response = AsyncFuture::new(deferred_request.perform()?).await?;
-            let status = response.StatusCode().unwrap().0;
-            if status >299 || status < 200 {
-                return Err(Error::StatusCode(status as u16));
-            }
-            let content_stream = response.Content().unwrap();
-
-            //get temporary directory
-            let mut buf: MaybeUninit<[u16; MAX_PATH as usize +1]> = MaybeUninit::uninit();
-            let r = unsafe {
-                let lpbuffer = PWSTR(buf.assume_init_mut().as_mut_ptr());
-                GetTempPathW(buf.assume_init().len() as u32,lpbuffer)
-            };
-            if r == 0 {
-                return Err(Error::PcoreError(pcore::error::Error::win32_last()))
-            }
-            //get temporary path
-            let mut filepath: MaybeUninit<[u16; MAX_PATH as usize +1]> = MaybeUninit::uninit();
-            let r= unsafe {
-                let pathbuf = PWSTR(buf.assume_init_mut().as_mut_ptr());
-                let prefixstring: PWSTR = std::mem::transmute(pcore::pstr!("drs").into_unsafe_const_pwzstr());
-                GetTempFileNameW(pathbuf,prefixstring, 0, PWSTR(filepath.assume_init_mut().as_mut_ptr()))
-            };
-            if r == 0 {
-                return Err(Error::PcoreError(pcore::error::Error::win32_last()))
-            }
-            let tempfile_str = unsafe{U16ZErasedLength::with_u16_z_unknown_length(filepath.assume_init_mut()).find_length().to_owned()};
-            let mut header = MaybeUninit::uninit();
-            let winfile = AsyncFuture::new(StorageFile::GetFileFromPathAsync(&unsafe{tempfile_str.into_hstring_trampoline(header.assume_init_mut())}).unwrap()).await?;
-            let opened_file = AsyncFuture::new(winfile.OpenAsync(FileAccessMode::ReadWrite)?).await?;
-            let output_stream = opened_file.GetOutputStreamAt(0).unwrap();
-            use windows::core::Interface;
-            let output_stream: IOutputStream = output_stream.cast().unwrap();
-            AsyncFuture::new(content_stream.WriteToStreamAsync(output_stream)?).await?;
-            println!("wrote to tempfile_str {:?}",tempfile_str);
-            Ok(Downloaded(tempfile_str))
-        }
+///Downloads the request into a file.
+///
+/// The file will be located in a temporary directory and will be deleted when the return value is dropped.
+fn download(client: HttpClient, request: RequestDescription) -> impl Future<Output=Result<Downloaded,Error>> {
+    let file_name = request.file_name.clone();
+    let deferred_request = DeferredRequest::new(request);
+    async move {
+        use windows::Storage::{StorageFile,FileAccessMode};
+        use windows::Storage::Streams::IOutputStream;
+        let (operation, _spool) = deferred_request.perform(&client).await?;
+        let _cancel = CancelOnDrop::new(&operation)?;
+        let response = AsyncFuture::new(operation).await?;
+        let metadata = metadata(&response)?;
+        let content_stream = response.Content()?;
 
+        let dir = tempdir().map_err(Error::Io)?;
+        let path = dir.path().join(file_name);
+        //GetFileFromPathAsync requires the file to exist
+        std::fs::File::create(&path).map_err(Error::Io)?;
+        let path_str = path.to_str().ok_or_else(|| Error::InvalidURL(path.to_string_lossy().into_owned()))?;
+        let winfile = AsyncFuture::new(StorageFile::GetFileFromPathAsync(&HSTRING::from(path_str))?).await?;
+        let opened_file = AsyncFuture::new(winfile.OpenAsync(FileAccessMode::ReadWrite)?).await?;
+        let output_stream = opened_file.GetOutputStreamAt(0)?;
+        let output_stream: IOutputStream = output_stream.cast()?;
+        let operation = content_stream.WriteToStreamAsync(output_stream)?;
+        let _cancel = CancelOnDrop::new(&operation)?;
+        AsyncFuture::new(operation).await?;
+        //release our handle so the file can be read and deleted
+        drop(opened_file);
+        Ok(Downloaded::new(dir, path, metadata))
     }
-
 }
+
 ///This is a request that is not yet made.  We move the builder type into this.
-struct DeferredRequest<'a> {
-    url: ParameterString<'a>,
-    headers: HashMap<ParameterString<'a>,ParameterString<'a>>,
-    body: Option<WinBuffer>,
-    method: ParameterString<'a>,
+struct DeferredRequest {
+    url: String,
+    headers: HeaderMap,
+    body: Option<Body>,
+    method: Method,
 }
-impl<'a> DeferredRequest<'a> {
-    fn new(request: Request<'a>) -> Self {
+impl DeferredRequest {
+    fn new(request: RequestDescription) -> Self {
         DeferredRequest {
-            url: request.url,
+            url: request.url.to_string(),
             headers: request.headers,
             body: request.body,
             method: request.method,
         }
     }
-    fn perform(self) -> Result<IAsyncOperationWithProgress<HttpResponseMessage, HttpProgress>,Error> {
-        use windows::Web::Http::{HttpClient,HttpRequestMessage,HttpMethod};
+    ///Sends the request.  A body read from files is streamed from a temporary copy, which must be kept until the
+    /// operation completes.
+    async fn perform(self, client: &HttpClient) -> Result<(IAsyncOperationWithProgress<HttpResponseMessage, HttpProgress>, Option<TempPath>),Error> {
+        use windows::Web::Http::{HttpRequestMessage,HttpMethod};
         use windows::Foundation::Uri;
-        let client = HttpClient::new().unwrap();
-        let headers = client.DefaultRequestHeaders().unwrap();
-        let mut str_header = MaybeUninit::uninit();
-        let useragent = unsafe{pstr!("drewcrawford/requestr 0.1 (rust)").into_hstring_trampoline(&mut str_header)};
-        headers.UserAgent().unwrap().ParseAdd(&useragent).unwrap();
-        for header in self.headers {
-            unsafe {
-                let mut key_header = MaybeUninit::uninit();
-                let mut value_header = MaybeUninit::uninit();
-                let key_hstr = header.0.into_hstring_trampoline(&mut key_header);
-                let val_hstr = header.1.into_hstring_trampoline(&mut value_header);
-                headers.Append(&key_hstr,&val_hstr).unwrap();
+        let uri = Uri::CreateUri(&HSTRING::from(self.url.as_str())).map_err(|_| Error::InvalidURL(self.url.clone()))?;
+        let request_message = HttpRequestMessage::new().unwrap();
+        let (content, spool) = match self.body {
+            Some(body) => match body.into_bytes() {
+                Ok(bytes) => (Some(WinBuffer(bytes).as_http_buffer()), None),
+                Err(body) => {
+                    let (content, spool) = stream_content(body).await?;
+                    (Some(content), Some(spool))
+                }
+            },
+            None => (None, None),
+        };
+        //the client is shared, so headers go on the message rather than the client's defaults
+        let headers = request_message.Headers().unwrap();
+        for (name, value) in self.headers.combined() {
+            //fields are validated before we get here, but WinRT parses some of them and may still refuse
+            let invalid = |_| Error::InvalidHeader(name.to_owned());
+            match &content {
+                //WinRT keeps fields that describe the body, such as `Content-Type`, with the content
+                Some(content) if is_content_header(name) => {
+                    content.Headers()?.Append(&HSTRING::from(name),&HSTRING::from(value.as_str())).map_err(invalid)?;
+                }
+                _ => headers.Append(&HSTRING::from(name),&HSTRING::from(value.as_str())).map_err(invalid)?,
             }
         }
 
-        let mut str_header = MaybeUninit::uninit();
-        let uri = Uri::CreateUri(&unsafe{self.url.into_hstring_trampoline(&mut str_header)})?;
-        let request_message = HttpRequestMessage::new().unwrap();
-        let mut str_header = MaybeUninit::uninit();
-
-        let http_method = HttpMethod::Create(unsafe{&self.method.into_hstring_trampoline(&mut str_header)}).unwrap();
+        let http_method = HttpMethod::Create(&HSTRING::from(self.method.as_str())).unwrap();
         request_message.SetMethod(http_method).unwrap();
         request_message.SetRequestUri(uri).unwrap();
-        match self.body {
-            None => {}
-            Some(body) => {
-                request_message.SetContent(body.as_http_buffer()).unwrap();
-            }
+        if let Some(content) = content {
+            request_message.SetContent(content).unwrap();
         }
         let response = client.SendRequestAsync(request_message)?;
-        Ok(response)
+        Ok((response, spool))
     }
 }
-#[cfg(test)] mod test {
-    use crate::Request;
-    use pcore::release_pool::autoreleasepool;
 
+///`HttpStreamContent` for a body read from files.
+///
+/// WinRT has no stream that joins several sources, so the body is copied into one temporary file, which is then
+/// streamed from disk.  Either way, only a buffer's worth of the body is in memory at once.
+async fn stream_content(body: Body) -> Result<(IHttpContent, TempPath),Error> {
+    use windows::Storage::{StorageFile,FileAccessMode};
+    use windows::Storage::Streams::IInputStream;
+    use windows::Web::Http::HttpStreamContent;
+    let mut spool = NamedTempFile::new().map_err(Error::Io)?;
+    std::io::copy(&mut body.reader(), &mut spool).map_err(Error::Io)?;
+    let spool = spool.into_temp_path();
+    let path_str = spool.to_str().ok_or_else(|| Error::Io(std::io::Error::new(std::io::ErrorKind::InvalidInput, "temporary path isn't UTF-8")))?;
+    let file = AsyncFuture::new(StorageFile::GetFileFromPathAsync(&HSTRING::from(path_str))?).await?;
+    //a random access stream knows its size, so the content gets a Content-Length rather than being chunked
+    let stream = AsyncFuture::new(file.OpenAsync(FileAccessMode::Read)?).await?;
+    let content = HttpStreamContent::CreateFromInputStream(&stream.cast::<IInputStream>()?)?;
+    Ok((content.cast()?, spool))
+}
+
+///Whether WinRT expects `name` on `HttpContentHeaderCollection` rather than on the message.
+fn is_content_header(name: &str) -> bool {
+    name.get(..8).is_some_and(|prefix| prefix.eq_ignore_ascii_case("content-")) ||
+        name.eq_ignore_ascii_case("expires") || name.eq_ignore_ascii_case("last-modified")
+}
+
+///Creates an `HttpClient`, whose filter holds the connection pool.
+fn http_client(config: &SessionConfig) -> Result<HttpClient,Error> {
+    use windows::Web::Http::Filters::HttpBaseProtocolFilter;
+    use windows::Security::Cryptography::Certificates::ChainValidationResult;
+    //rather than quietly using the system's proxy
+    if config.proxy.is_some() {
+        return Err(Error::Unsupported("proxy on HttpClient".to_owned()));
+    }
+    let filter = HttpBaseProtocolFilter::new()?;
+    filter.SetAllowAutoRedirect(config.follow_redirects)?;
+    if config.accept_invalid_certs {
+        let ignorable = filter.IgnorableServerCertificateErrors()?;
+        for error in [ChainValidationResult::Untrusted, ChainValidationResult::Expired, ChainValidationResult::InvalidName] {
+            ignorable.Append(error)?;
+        }
+    }
+    //the User-Agent comes with the request, like every other header
+    Ok(HttpClient::Create(&filter)?)
+}
+
+///Performs requests with WinRT's `HttpClient`.
+///
+/// Each `HttpClientTransport` has its own `HttpClient`, and so its own connections.  `HttpClient` has no proxy
+/// setting; it uses the system's, and a [crate::Client] asking for one fails with [Error::Unsupported].
+pub struct HttpClientTransport {
+    client: HttpClient,
+}
+impl HttpClientTransport {
+    pub fn new() -> Self {
+        HttpClientTransport::with_session(&SessionConfig::default()).expect("HttpClient")
+    }
+    pub(crate) fn with_session(config: &SessionConfig) -> Result<Self,Error> {
+        Ok(HttpClientTransport { client: http_client(config)? })
+    }
+}
+impl Default for HttpClientTransport {
+    fn default() -> Self {
+        HttpClientTransport::new()
+    }
+}
+impl Transport for HttpClientTransport {
+    //The futures hold Windows.Web.Http and Windows.Storage objects and their async operations, which are all agile,
+    //so they may be used from any thread.
+    fn perform(&self, request: RequestDescription) -> BoxFuture<Result<Response,Error>> {
+        Box::pin(unsafe { AssertSend::new(perform(self.client.clone(), request)) })
+    }
+    fn download(&self, request: RequestDescription) -> BoxFuture<Result<Downloaded,Error>> {
+        Box::pin(unsafe { AssertSend::new(download(self.client.clone(), request)) })
+    }
+}
+
+#[cfg(test)] mod test {
+    use crate::{Request, Method};
+    use crate::test_server::{TestServer, large_body};
     use pcore::pstr;
+    use pcore::release_pool::autoreleasepool;
     #[test] fn github() {
+        let server = TestServer::start();
         autoreleasepool(|pool| {
-            let r = Request::new(pstr!("https://sealedabstract.com"),pool).unwrap();
+            let r = Request::new(server.url("/echo/headers"), pool).unwrap();
             let future = r
-                .header(pstr!("Accept"),Some(pstr!("application/vnd.github.v3+json")),pool)
-                .header(pstr!("Authorization"), Some(pstr!("token foobar")),pool)
+                .header(pstr!("Accept"),Some(pstr!("application/vnd.github.v3+json")), pool)
+                .header(pstr!("Authorization"), Some(pstr!("token foobar")), pool)
                 .perform(pool);
-            let result = kiruna::test::test_await(future, std::time::Duration::from_secs(5));
+            let result = kiruna::test::test_await(future, std::time::Duration::from_secs(10));
             let response = result.unwrap();
-            let data = response.check_status(pool).unwrap();
-            println!("{:?}",data);
+            let data = response.check_status().unwrap();
+            let headers = String::from_utf8_lossy(data.as_slice()).to_ascii_lowercase();
+            assert!(headers.contains("accept: application/vnd.github.v3+json\r\n"));
+            assert!(headers.contains("authorization: token foobar\r\n"));
+        });
+    }
+
+    #[test] fn post_body() {
+        let server = TestServer::start();
+        autoreleasepool(|pool| {
+            let future = Request::new(server.url("/echo"), pool).unwrap()
+                .method(Method::Put)
+                .body(Box::new(*b"payload"))
+                .perform(pool);
+            let response = kiruna::test::test_await(future, std::time::Duration::from_secs(10)).unwrap();
+            assert_eq!(response.check_status().unwrap().as_slice(), b"payload");
+            let future = Request::new(server.url("/status/404"), pool).unwrap().perform(pool);
+            let response = kiruna::test::test_await(future, std::time::Duration::from_secs(10)).unwrap();
+            assert_eq!(response.check_status().unwrap_err().0, 404);
         });
     }
 
     #[test] fn download() {
+        let server = TestServer::start();
         autoreleasepool(|pool| {
-            let r = Request::new(pstr!("https://sealedabstract.com/index.html"),pool).unwrap();
+            let r = Request::new(server.url("/large/100000"), pool).unwrap();
             let future = r
                 .download(pool);
 
             let result = kiruna::test::test_await(future, std::time::Duration::from_secs(10));
             let response = result.unwrap();
-            println!("{:?}", response);
+            response.check_status().unwrap();
+            let path = response.copy_path();
+            assert_eq!(path.file_name().unwrap(), "100000");
+            assert_eq!(std::fs::read(&path).unwrap(), large_body(100000));
+            drop(response);
+            assert!(!path.exists());
         });
     }
-}
 
+    #[test] fn download_status() {
+        let server = TestServer::start();
+        autoreleasepool(|pool| {
+            let future = Request::new(server.url("/status/500"), pool).unwrap().download(pool);
+            let response = kiruna::test::test_await(future, std::time::Duration::from_secs(10)).unwrap();
+            assert!(matches!(response.check_status(), Err(crate::Error::StatusCode(s)) if s == 500));
+        });
+    }
+}